@@ -0,0 +1,197 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Summary statistics of latency samples in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// Mean absolute difference between consecutive samples.
+    pub jitter: f64,
+}
+
+/// The nearest-rank percentile `p` (0-100) of sorted samples.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl Summary {
+    /// Summarizes samples given in the order they were measured.
+    pub fn from_samples(samples: &[f64]) -> Option<Summary> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let jitter = if samples.len() > 1 {
+            samples.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (samples.len() - 1) as f64
+        } else {
+            0.0
+        };
+        Some(Summary {
+            count: samples.len(),
+            min: sorted[0],
+            mean: samples.iter().sum::<f64>() / samples.len() as f64,
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+            jitter,
+        })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {:.3}ms  mean {:.3}ms  p50 {:.3}ms  p95 {:.3}ms  p99 {:.3}ms  max {:.3}ms  jitter {:.3}ms",
+            self.min, self.mean, self.p50, self.p95, self.p99, self.max, self.jitter
+        )
+    }
+}
+
+/// Counts samples in `buckets` equal ranges between the minimum and maximum.
+/// Returns the lower and upper bound and the count of each range.
+pub fn histogram(samples: &[f64], buckets: usize) -> Vec<(f64, f64, usize)> {
+    if samples.is_empty() || buckets == 0 {
+        return Vec::new();
+    }
+    let min = samples.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let width = if max > min { (max - min) / buckets as f64 } else { 1.0 };
+    let mut counts = vec![0; buckets];
+    for sample in samples {
+        let bucket = (((sample - min) / width) as usize).min(buckets - 1);
+        counts[bucket] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| (min + i as f64 * width, min + (i + 1) as f64 * width, count))
+        .collect()
+}
+
+/// Text lines with a bar per histogram range, the longest bar `width` characters.
+pub fn render_histogram(histogram: &[(f64, f64, usize)], width: usize) -> Vec<String> {
+    let most = histogram.iter().map(|(_, _, count)| *count).max().unwrap_or(0).max(1);
+    histogram
+        .iter()
+        .map(|(low, high, count)| {
+            let bar = "#".repeat((count * width).div_ceil(most));
+            format!("{:9.3} - {:9.3} ms |{:<width$}| {}", low, high, bar, count, width = width)
+        })
+        .collect()
+}
+
+/// The message a round-trip probe is sent as; the sequence number is encoded in
+/// its data bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiProbe {
+    /// Non-commercial SysEx with a 28-bit sequence number.
+    SysEx,
+    /// Pitch bend on channel 16 with a 14-bit sequence number, for devices or
+    /// routes which do not pass SysEx.
+    PitchBend,
+}
+
+const PROBE_SYSEX_HEADER: [u8; 5] = [0xF0, 0x7D, b'm', b'o', b't'];
+
+impl MidiProbe {
+    /// The number of distinct sequence numbers.
+    pub fn sequence_count(&self) -> u32 {
+        match self {
+            MidiProbe::SysEx => 1 << 28,
+            MidiProbe::PitchBend => 1 << 14,
+        }
+    }
+
+    pub fn encode(&self, sequence: u32) -> Vec<u8> {
+        let byte = |shift: u32| ((sequence >> shift) & 0x7F) as u8;
+        match self {
+            MidiProbe::SysEx => {
+                let mut message = PROBE_SYSEX_HEADER.to_vec();
+                message.extend([byte(21), byte(14), byte(7), byte(0), 0xF7]);
+                message
+            }
+            MidiProbe::PitchBend => vec![0xEF, byte(0), byte(7)],
+        }
+    }
+
+    /// The sequence number of a returning probe; None for other messages.
+    pub fn decode(&self, message: &[u8]) -> Option<u32> {
+        match self {
+            MidiProbe::SysEx => match message {
+                [header @ .., a, b, c, d, 0xF7] if header == PROBE_SYSEX_HEADER => {
+                    Some((*a as u32) << 21 | (*b as u32) << 14 | (*c as u32) << 7 | *d as u32)
+                }
+                _ => None,
+            },
+            MidiProbe::PitchBend => match message {
+                [0xEF, lsb, msb] => Some((*msb as u32) << 7 | *lsb as u32),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl FromStr for MidiProbe {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<MidiProbe, String> {
+        match text.to_lowercase().as_str() {
+            "sysex" => Ok(MidiProbe::SysEx),
+            "pitchbend" => Ok(MidiProbe::PitchBend),
+            _ => Err(String::from("Expects one of: sysex, pitchbend")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let samples: Vec<f64> = (1..=100).map(|i| i as f64).collect();
+        let summary = Summary::from_samples(&samples).unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!((summary.min, summary.max), (1.0, 100.0));
+        assert_eq!(summary.mean, 50.5);
+        assert_eq!((summary.p50, summary.p95, summary.p99), (50.0, 95.0, 99.0));
+        assert_eq!(summary.jitter, 1.0);
+        assert_eq!(Summary::from_samples(&[]), None);
+        assert_eq!(Summary::from_samples(&[2.0]).unwrap().p99, 2.0);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let buckets = histogram(&[1.0, 1.5, 2.0, 2.9, 3.0], 2);
+        assert_eq!(buckets, vec![(1.0, 2.0, 2), (2.0, 3.0, 3)]);
+        assert_eq!(histogram(&[4.0, 4.0], 3)[0].2, 2);
+        let lines = render_histogram(&buckets, 6);
+        assert_eq!(lines[0], "    1.000 -     2.000 ms |####  | 2");
+        assert_eq!(lines[1], "    2.000 -     3.000 ms |######| 3");
+    }
+
+    #[test]
+    fn test_probe_round_trip() {
+        for probe in [MidiProbe::SysEx, MidiProbe::PitchBend] {
+            for sequence in [0, 1, 127, 128, 16383] {
+                assert_eq!(probe.decode(&probe.encode(sequence)), Some(sequence));
+            }
+        }
+        assert_eq!(MidiProbe::SysEx.decode(&MidiProbe::SysEx.encode(1 << 27)), Some(1 << 27));
+        assert_eq!(MidiProbe::SysEx.decode(&[0xF0, 0x7D, 1, 2, 3, 4, 5, 0xF7]), None);
+        assert_eq!(MidiProbe::PitchBend.decode(&[0xE0, 1, 2]), None);
+    }
+}
@@ -1,28 +1,47 @@
 mod mdns_service_manager;
+mod midi_clock;
+mod midi_filter;
 mod midi_io;
 mod osc_io;
+mod osc_schedule;
+mod osc_syntax;
+mod latency;
 mod lua_processor;
+mod mtc;
+mod output_format;
+mod resolver;
+mod smf;
+mod sysex;
+mod timing;
 
-use clap::{Arg, Command};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use midi_clock::ClockCommand;
+use midi_io::{MidiMessage, MidiPort, PortSpec};
+use output_format::OutputFormat;
 use osc_io::OscSender;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Write;
 use std::io::{self, BufRead};
-use std::net::SocketAddrV4;
+use std::net::SocketAddr;
 use std::str::FromStr;
 
 use rosc::OscPacket;
 use rosc::OscType;
 
-use once_cell::sync::OnceCell;
-
 use std::sync::mpsc::channel;
 use std::sync::Mutex;
 
-use std::ops::DerefMut;
-
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time for the input ports to connect before sending a message which is answered,
+/// like a dump request or a latency probe.
+const INPUT_CONNECT_DELAY: Duration = Duration::from_millis(200);
 
 struct MidiRoundTrip {
     midi_in: midi_io::MidiIn,
@@ -30,175 +49,931 @@ struct MidiRoundTrip {
 }
 
 impl MidiRoundTrip {
-    fn new(midi_in_port_index: usize, midi_out_port_index: usize) -> MidiRoundTrip {
+    fn new(midi_in: midi_io::MidiIn, midi_out_ports: Vec<MidiPort>) -> MidiRoundTrip {
         MidiRoundTrip {
-            midi_in: midi_io::MidiIn::new(midi_in_port_index),
-            midi_out: midi_io::MidiOut::new(midi_out_port_index),
+            midi_in,
+            midi_out: midi_io::MidiOut::new(midi_out_ports),
         }
     }
 
-    fn respond_to_midi(mut self) {
-        self.midi_in.listen(
+    fn respond_to_midi(mut self, running: Arc<AtomicBool>) {
+        self.midi_in.listen_with_interrupt(
             move |_time_stamp, message, _| {
                 self.midi_out.send_full(message);
             },
             (),
+            running,
+        );
+    }
+}
+
+/// Options of a self-contained round-trip latency measurement.
+struct ProbeOptions {
+    probe: latency::MidiProbe,
+    count: u32,
+    interval: Duration,
+    /// How long to wait for the last probes to return.
+    timeout: Duration,
+    csv_path: Option<String>,
+}
+
+/// Sends sequence-numbered probes and matches them when they come back, e.g.
+/// through a loopback cable or a device echoing its input.
+struct MidiLatencyProbe {
+    midi_in: midi_io::MidiIn,
+    midi_out: midi_io::MidiOut,
+    options: ProbeOptions,
+}
+
+impl MidiLatencyProbe {
+    fn new(midi_in: midi_io::MidiIn, midi_out_ports: Vec<MidiPort>, options: ProbeOptions) -> MidiLatencyProbe {
+        MidiLatencyProbe {
+            midi_in,
+            midi_out: midi_io::MidiOut::new(midi_out_ports),
+            options,
+        }
+    }
+
+    fn measure(self, running: Arc<AtomicBool>) {
+        let options = self.options;
+        let count = options.count as usize;
+        let sent: Arc<Mutex<Vec<Option<Instant>>>> = Arc::new(Mutex::new(vec![None; count]));
+        let received: Arc<Mutex<Vec<Option<Duration>>>> = Arc::new(Mutex::new(vec![None; count]));
+        let listening = Arc::new(AtomicBool::new(true));
+
+        println!(
+            "Sending {} probes every {}ms, press Ctrl+C to stop early",
+            count,
+            options.interval.as_secs_f64() * 1000.0
         );
+        let sender = {
+            let mut midi_out = self.midi_out;
+            let sent = sent.clone();
+            let listening = listening.clone();
+            let probe = options.probe;
+            let (interval, timeout) = (options.interval, options.timeout);
+            thread::spawn(move || {
+                thread::sleep(INPUT_CONNECT_DELAY);
+                let begin = Instant::now();
+                for sequence in 0..count {
+                    if !timing::sleep_until(begin + interval * sequence as u32, &running) {
+                        break;
+                    }
+                    sent.lock().unwrap()[sequence] = Some(Instant::now());
+                    midi_out.send_full(&probe.encode(sequence as u32));
+                }
+                timing::sleep_until(Instant::now() + timeout, &running);
+                listening.store(false, Ordering::SeqCst);
+            })
+        };
+
+        let probe = options.probe;
+        let receive_sent = sent.clone();
+        let receive = received.clone();
+        self.midi_in.listen_with_interrupt(
+            move |_, message, _| {
+                let now = Instant::now();
+                if let Some(sequence) = probe.decode(message).map(|s| s as usize).filter(|s| *s < count) {
+                    let sent_at = receive_sent.lock().unwrap()[sequence];
+                    let mut received = receive.lock().unwrap();
+                    if let (Some(sent_at), None) = (sent_at, received[sequence]) {
+                        received[sequence] = Some(now - sent_at);
+                    }
+                }
+            },
+            (),
+            listening.clone(),
+        );
+        sender.join().unwrap();
+
+        let sent = sent.lock().unwrap();
+        let received = received.lock().unwrap();
+        let sent_count = sent.iter().filter(|s| s.is_some()).count();
+        let samples: Vec<f64> = received.iter().flatten().map(|d| d.as_secs_f64() * 1000.0).collect();
+        let lost = sent_count - samples.len();
+        println!(
+            "Sent {} probes, received {}, lost {} ({:.1}%)",
+            sent_count,
+            samples.len(),
+            lost,
+            if sent_count > 0 { lost as f64 * 100.0 / sent_count as f64 } else { 0.0 }
+        );
+        if let Some(summary) = latency::Summary::from_samples(&samples) {
+            println!("{}", summary);
+            for line in latency::render_histogram(&latency::histogram(&samples, 10), 40) {
+                println!("{}", line);
+            }
+        }
+
+        if let Some(csv_path) = options.csv_path {
+            let first = sent.iter().flatten().next().copied();
+            let mut csv = String::from("sequence,sent,latency_ms\n");
+            for (sequence, sent_at) in sent.iter().enumerate() {
+                if let (Some(sent_at), Some(first)) = (sent_at, first) {
+                    let latency = received[sequence].map(|d| format!("{:.6}", d.as_secs_f64() * 1000.0)).unwrap_or_default();
+                    csv.push_str(&format!("{},{:.6},{}\n", sequence, (*sent_at - first).as_secs_f64(), latency));
+                }
+            }
+            match fs::write(&csv_path, csv) {
+                Ok(_) => println!("Wrote samples to {}", csv_path),
+                Err(e) => println!("Could not write {}: {}", csv_path, e),
+            }
+        }
+    }
+}
+
+/// Address of OSC latency probes, with the sequence number and the send time in
+/// microseconds since the start of the measurement as arguments.
+const OSC_PROBE_ADDRESS: &str = "/mot/latency";
+
+/// Sends probes to an OSC reflector and reports round-trip times, jitter,
+/// reordering and loss, every `report_interval` and at the end.
+struct OscLatencyProbe {
+    osc_sender: osc_io::OscSender,
+    interval: Duration,
+    count: Option<u64>,
+    timeout: Duration,
+    report_interval: Duration,
+}
+
+/// Counts of a reporting period or of the whole measurement.
+#[derive(Default)]
+struct ProbeCounts {
+    sent: u64,
+    received: u64,
+    lost: u64,
+    reordered: u64,
+    late: u64,
+    samples: Vec<f64>,
+}
+
+impl ProbeCounts {
+    fn print(&self, label: &str) {
+        println!(
+            "{}sent {}  received {}  lost {}  reordered {}  late {}",
+            label, self.sent, self.received, self.lost, self.reordered, self.late
+        );
+        if let Some(summary) = latency::Summary::from_samples(&self.samples) {
+            println!("{}{}", " ".repeat(label.len()), summary);
+        }
+    }
+}
+
+impl OscLatencyProbe {
+    fn new(osc_host_address: &str, rate: f64, count: Option<u64>, timeout: Duration, report_interval: Duration) -> OscLatencyProbe {
+        OscLatencyProbe {
+            osc_sender: osc_io::OscSender::new(osc_host_address.to_string()),
+            interval: Duration::from_secs_f64(1.0 / rate),
+            count,
+            timeout,
+            report_interval,
+        }
+    }
+
+    fn measure(self, running: Arc<AtomicBool>) {
+        let begin = Instant::now();
+        // Probes waiting for their reply, by sequence number
+        let mut pending: BTreeMap<u64, Instant> = BTreeMap::new();
+        let mut next_sequence: u64 = 0;
+        let mut highest_received: Option<u64> = None;
+        let mut period = ProbeCounts::default();
+        let mut total = ProbeCounts::default();
+        let mut next_report = begin + self.report_interval;
+
+        println!("Probing {} every {:.1}ms, press Ctrl+C to stop", OSC_PROBE_ADDRESS, self.interval.as_secs_f64() * 1000.0);
+        while running.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            let done_sending = self.count.is_some_and(|count| next_sequence >= count);
+            if done_sending && pending.is_empty() {
+                break;
+            }
+
+            // Probes without reply in time are lost
+            while let Some((&sequence, &sent_at)) = pending.iter().next() {
+                if now < sent_at + self.timeout {
+                    break;
+                }
+                pending.remove(&sequence);
+                period.lost += 1;
+                total.lost += 1;
+            }
+
+            let next_send = begin + self.interval.mul_f64(next_sequence as f64);
+            if !done_sending && now >= next_send {
+                let micros = (now - begin).as_micros() as i64;
+                self.osc_sender.send(
+                    OSC_PROBE_ADDRESS.to_string(),
+                    vec![OscType::Int(next_sequence as i32), OscType::Long(micros)],
+                );
+                pending.insert(next_sequence, now);
+                next_sequence += 1;
+                period.sent += 1;
+                total.sent += 1;
+                continue;
+            }
+
+            if now >= next_report {
+                period.print(&format!("{:7.1}s  ", (now - begin).as_secs_f64()));
+                period = ProbeCounts::default();
+                next_report += self.report_interval;
+                continue;
+            }
+
+            let mut wake_up = next_report;
+            if !done_sending {
+                wake_up = wake_up.min(next_send);
+            }
+            if let Some(sent_at) = pending.values().next() {
+                wake_up = wake_up.min(*sent_at + self.timeout);
+            }
+            let reply = self.osc_sender.receive(wake_up.saturating_duration_since(now));
+            let sequence = match reply {
+                Some((OscPacket::Message(msg), _)) if msg.addr == OSC_PROBE_ADDRESS => match msg.args.first() {
+                    Some(OscType::Int(sequence)) => *sequence as u64,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let received_at = Instant::now();
+            match pending.remove(&sequence) {
+                Some(sent_at) => {
+                    let rtt = (received_at - sent_at).as_secs_f64() * 1000.0;
+                    if highest_received.is_some_and(|highest| sequence < highest) {
+                        period.reordered += 1;
+                        total.reordered += 1;
+                    }
+                    highest_received = highest_received.max(Some(sequence));
+                    for counts in [&mut period, &mut total] {
+                        counts.received += 1;
+                        counts.samples.push(rtt);
+                    }
+                }
+                // Already counted as lost, or a duplicate
+                None => {
+                    period.late += 1;
+                    total.late += 1;
+                }
+            }
+        }
+
+        println!();
+        total.print("Total  ");
+        for line in latency::render_histogram(&latency::histogram(&total.samples, 10), 40) {
+            println!("{}", line);
+        }
     }
 }
 
 struct MidiEcho {
     midi_in: midi_io::MidiIn,
+    raw: bool,
+    format: OutputFormat,
 }
 
 impl MidiEcho {
-    fn new(midi_in_port_index: usize) -> MidiEcho {
+    fn new(midi_in: midi_io::MidiIn, raw: bool, format: OutputFormat) -> MidiEcho {
         MidiEcho {
-            midi_in: midi_io::MidiIn::new(midi_in_port_index),
+            midi_in,
+            raw,
+            format,
         }
     }
 
-    fn echo_midi(self) {
+    fn echo_midi(self, running: Arc<AtomicBool>) {
         let mut message_index = 0;
-        self.midi_in.listen(
-            move |time_stamp, message, _| {
-                println!("{} {} {:?}", message_index, time_stamp, message);
-                message_index = message_index + 1;
+        let raw = self.raw;
+        let format = self.format;
+        if format == OutputFormat::Csv {
+            println!("{}", output_format::MIDI_CSV_HEADER);
+        }
+        self.midi_in.listen_with_source(
+            move |source, time_stamp, message, _| {
+                if format != OutputFormat::Text {
+                    let time = output_format::now_seconds();
+                    println!("{}", output_format::midi_record(format, time, time_stamp, source, message));
+                    return;
+                }
+                match MidiMessage::parse(message) {
+                    Ok(midi_message) if !raw => {
+                        println!("{} {} {}", message_index, time_stamp, midi_message)
+                    }
+                    _ => println!("{} {} {:?}", message_index, time_stamp, message),
+                }
+                message_index += 1;
             },
             (),
+            running,
         );
     }
 }
 
-struct OscToMidi {
+struct MidiRecorder {
+    midi_in: midi_io::MidiIn,
+    file_path: String,
+    options: smf::RecordOptions,
+}
+
+impl MidiRecorder {
+    fn new(midi_in: midi_io::MidiIn, file_path: &str, options: smf::RecordOptions) -> MidiRecorder {
+        MidiRecorder {
+            midi_in,
+            file_path: file_path.to_string(),
+            options,
+        }
+    }
+
+    /// Records until Ctrl+C and then writes the Standard MIDI File.
+    fn record(self, running: Arc<AtomicBool>) {
+        let messages = Arc::new(Mutex::new(Vec::<(u64, Vec<u8>)>::new()));
+        let recorded = messages.clone();
+        let mut clock = smf::RecordingClock::new();
+        let start = Instant::now();
+        println!("Recording MIDI to {}, press Ctrl+C to stop", self.file_path);
+        self.midi_in.listen_with_source(
+            move |source, time_stamp, message, _| {
+                let time = clock.stamp(source, time_stamp, start.elapsed().as_micros() as u64);
+                recorded.lock().unwrap().push((time, message.to_vec()));
+            },
+            (),
+            running,
+        );
+
+        // Messages of different ports can arrive slightly out of order
+        let mut messages = messages.lock().unwrap();
+        messages.sort_by_key(|(time, _)| *time);
+        let smf = smf::Smf::from_recording(&messages, &self.options);
+        match fs::write(&self.file_path, smf.encode()) {
+            Ok(_) => println!("Wrote {} MIDI messages to {}", messages.len(), self.file_path),
+            Err(e) => eprintln!("Could not write {}: {}", self.file_path, e),
+        }
+    }
+}
+
+struct MidiPlayer {
     midi_out: midi_io::MidiOut,
-    verbose: bool,
-    osc_host_address: String,
-    osc_path_address: String,
+    /// Messages with their time in microseconds since the start of the file.
+    messages: Vec<(u64, Vec<u8>)>,
+    speed: f64,
+    looping: bool,
+    /// Start offset in microseconds.
+    start: u64,
 }
 
-unsafe impl Sync for OscToMidi {}
+impl MidiPlayer {
+    /// Prepares the messages of a file, leaving out the channel messages of muted
+    /// channels (one based).
+    fn new(midi_out_ports: Vec<MidiPort>, smf: &smf::Smf, muted_channels: &[u8], speed: f64, looping: bool, start: f64) -> MidiPlayer {
+        let messages = smf
+            .timed_messages()
+            .into_iter()
+            .filter(|(_, message)| {
+                let channel = MidiMessage::parse(message).ok().and_then(|m| m.channel());
+                !channel.is_some_and(|c| muted_channels.contains(&(c + 1)))
+            })
+            .collect();
+        MidiPlayer {
+            midi_out: midi_io::MidiOut::new(midi_out_ports),
+            messages,
+            speed,
+            looping,
+            start: (start * 1_000_000.0) as u64,
+        }
+    }
+
+    /// Plays the file, once or until Ctrl+C when looping. Sounding notes are
+    /// stopped when playback is interrupted.
+    fn play(mut self, running: Arc<AtomicBool>) {
+        let length = self.messages.last().map_or(0, |(time, _)| *time);
+        println!(
+            "Playing {} MIDI messages, {:.1}s at speed {}",
+            self.messages.len(),
+            length.saturating_sub(self.start) as f64 / 1_000_000.0 / self.speed,
+            self.speed
+        );
+
+        let mut sounding: HashSet<(u8, u8)> = HashSet::new();
+        loop {
+            let begin = Instant::now();
+            let mut interrupted = false;
+            for (time, message) in &self.messages {
+                let decoded = MidiMessage::parse(message).ok();
+                if *time < self.start {
+                    // Skip notes before the start offset but restore the state of
+                    // controllers, programs and other settings
+                    if !matches!(
+                        decoded,
+                        Some(MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } | MidiMessage::PolyAftertouch { .. })
+                    ) {
+                        self.midi_out.send_full(message);
+                    }
+                    continue;
+                }
+                let offset = Duration::from_secs_f64((time - self.start) as f64 / 1_000_000.0 / self.speed);
+                if !timing::sleep_until(begin + offset, &running) {
+                    interrupted = true;
+                    break;
+                }
+                self.midi_out.send_full(message);
+                match decoded {
+                    Some(MidiMessage::NoteOn { channel, note, velocity }) if velocity > 0 => {
+                        sounding.insert((channel, note));
+                    }
+                    Some(MidiMessage::NoteOn { channel, note, .. } | MidiMessage::NoteOff { channel, note, .. }) => {
+                        sounding.remove(&(channel, note));
+                    }
+                    _ => {}
+                }
+            }
 
-impl core::fmt::Debug for OscToMidi {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        f.debug_struct("OSC to MIDI")
-            .field("verbose", &self.verbose)
-            .field("osc_host_address", &self.osc_host_address)
-            .finish()
+            for (channel, note) in sounding.drain() {
+                self.midi_out.send_full(&MidiMessage::NoteOff { channel, note, velocity: 0 }.encode());
+            }
+            if interrupted {
+                for channel in 0..16 {
+                    let all_notes_off = MidiMessage::ControlChange { channel, controller: 123, value: 0 };
+                    self.midi_out.send_full(&all_notes_off.encode());
+                }
+                println!("Stopped playback");
+                return;
+            }
+            if !self.looping {
+                return;
+            }
+        }
     }
 }
 
-static INSTANCE: OnceCell<Mutex<OscToMidi>> = OnceCell::new();
+struct SysExDumper {
+    midi_in: midi_io::MidiIn,
+    file_path: String,
+    split: bool,
+    count: Option<usize>,
+    request: Option<(midi_io::MidiOut, Vec<u8>)>,
+}
+
+impl SysExDumper {
+    fn new(midi_in: midi_io::MidiIn, file_path: &str, split: bool, count: Option<usize>) -> SysExDumper {
+        SysExDumper {
+            midi_in,
+            file_path: file_path.to_string(),
+            split,
+            count,
+            request: None,
+        }
+    }
+
+    /// Sends `request`, e.g. a dump request, once listening has started.
+    fn with_request(mut self, midi_out_ports: Vec<MidiPort>, request: Vec<u8>) -> SysExDumper {
+        self.request = Some((midi_io::MidiOut::new(midi_out_ports), request));
+        self
+    }
+
+    /// Saves incoming SysEx messages until Ctrl+C or until `count` messages arrived.
+    /// Other messages are ignored.
+    fn dump(self, running: Arc<AtomicBool>) {
+        let mut file = None;
+        if !self.split {
+            match fs::File::create(&self.file_path) {
+                Ok(f) => file = Some(f),
+                Err(e) => {
+                    println!("Could not create {}: {}", self.file_path, e);
+                    return;
+                }
+            }
+        }
+
+        if let Some((mut midi_out, request)) = self.request {
+            thread::spawn(move || {
+                thread::sleep(INPUT_CONNECT_DELAY);
+                midi_out.send_full(&request);
+                println!("Sent request {:02X?}", request);
+            });
+        }
+
+        println!("Waiting for SysEx messages, press Ctrl+C to stop");
+        let file_path = self.file_path;
+        let count = self.count;
+        let stop = running.clone();
+        let mut received = 0;
+        self.midi_in.listen_with_interrupt(
+            move |_, message, _| {
+                if message.first() != Some(&0xF0) {
+                    return;
+                }
+                received += 1;
+                let result = match file.as_mut() {
+                    Some(file) => file.write_all(message).map(|_| file_path.clone()),
+                    None => {
+                        let path = sysex::numbered_path(&file_path, received);
+                        fs::write(&path, message).map(|_| path)
+                    }
+                };
+                match result {
+                    Ok(path) => println!("SysEx message {} ({} bytes) saved to {}", received, message.len(), path),
+                    Err(e) => println!("Could not save SysEx message {}: {}", received, e),
+                }
+                if count == Some(received) {
+                    stop.store(false, Ordering::SeqCst);
+                }
+            },
+            (),
+            running,
+        );
+    }
+}
+
+struct MidiClock {
+    midi_out: midi_io::MidiOut,
+    bpm: f64,
+    playing: bool,
+}
+
+impl MidiClock {
+    fn new(midi_out_ports: Vec<MidiPort>, bpm: f64) -> MidiClock {
+        MidiClock {
+            midi_out: midi_io::MidiOut::new(midi_out_ports),
+            bpm,
+            playing: false,
+        }
+    }
+
+    fn queue_osc_command(packet: OscPacket, from_addr: SocketAddr, commands: &std::sync::mpsc::Sender<ClockCommand>) {
+        match packet {
+            OscPacket::Message(msg) => match ClockCommand::from_osc(&msg) {
+                Ok(command) => {
+                    let _ = commands.send(command);
+                }
+                Err(e) => println!("{} (from {})", e, from_addr),
+            },
+            OscPacket::Bundle(bundle) => {
+                for packet in bundle.content {
+                    MidiClock::queue_osc_command(packet, from_addr, commands);
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, command: ClockCommand, schedule: &mut midi_clock::TickSchedule) {
+        match command {
+            ClockCommand::Tempo(bpm) => {
+                schedule.set_bpm(bpm);
+                println!("Tempo {} BPM", bpm);
+            }
+            ClockCommand::Start => {
+                self.midi_out.send_full(&MidiMessage::Start.encode());
+                self.playing = true;
+                println!("Start");
+            }
+            ClockCommand::Continue => {
+                self.midi_out.send_full(&MidiMessage::Continue.encode());
+                self.playing = true;
+                println!("Continue");
+            }
+            ClockCommand::Stop => {
+                self.midi_out.send_full(&MidiMessage::Stop.encode());
+                self.playing = false;
+                println!("Stop");
+            }
+            ClockCommand::Position(position) if !self.playing => {
+                self.midi_out.send_full(&MidiMessage::SongPosition(position).encode());
+                println!("Song position {}", position);
+            }
+            ClockCommand::Position(_) => println!("Ignored song position while playing, stop first"),
+        }
+    }
+
+    /// Sends clock ticks until Ctrl+C. `commands` are applied at the first tick,
+    /// later ones arrive over OSC when an address to listen on is given.
+    fn run(mut self, commands: Vec<ClockCommand>, osc_address: Option<String>, running: Arc<AtomicBool>) {
+        // Clock controls received over OSC, applied at the next tick
+        let (command_sender, received_commands) = channel::<ClockCommand>();
+        for command in commands {
+            command_sender.send(command).unwrap();
+        }
+        if let Some(osc_address) = osc_address {
+            let running = running.clone();
+            thread::spawn(move || {
+                let (send, _recv) = channel::<u32>();
+                osc_io::OscServer::new(&osc_address, move |packet, from_addr| {
+                    MidiClock::queue_osc_command(packet, from_addr, &command_sender);
+                    0
+                })
+                .listen_with_interrupt(&send, running);
+            });
+        }
+
+        println!("Sending MIDI clock at {} BPM, press Ctrl+C to stop", self.bpm);
+        let mut schedule = midi_clock::TickSchedule::new(Instant::now(), self.bpm);
+        while running.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            if now > schedule.next() + midi_clock::tick_period(schedule.bpm()) * 2 {
+                println!("Clock fell behind, resynchronizing");
+                schedule.resync(now);
+            }
+            if !timing::sleep_until(schedule.next(), &running) {
+                break;
+            }
+            while let Ok(command) = received_commands.try_recv() {
+                self.apply(command, &mut schedule);
+            }
+            self.midi_out.send_full(&MidiMessage::TimingClock.encode());
+            schedule.advance();
+        }
+
+        if self.playing {
+            self.apply(ClockCommand::Stop, &mut schedule);
+        }
+    }
+}
+
+struct ClockMonitor {
+    midi_in: midi_io::MidiIn,
+    follower: midi_clock::ClockFollower,
+    osc_sender: Option<osc_io::OscSender>,
+}
+
+impl ClockMonitor {
+    fn new(midi_in: midi_io::MidiIn, beats_per_bar: u32, osc_host_address: Option<&str>) -> ClockMonitor {
+        ClockMonitor {
+            midi_in,
+            follower: midi_clock::ClockFollower::new(beats_per_bar),
+            osc_sender: osc_host_address.map(|address| osc_io::OscSender::new(address.to_string())),
+        }
+    }
+
+    /// Prints the tempo, position and tick jitter every beat until Ctrl+C.
+    fn monitor(self, running: Arc<AtomicBool>) {
+        let mut follower = self.follower;
+        let osc_sender = self.osc_sender;
+        println!("Waiting for MIDI clock, press Ctrl+C to stop");
+        self.midi_in.listen_with_interrupt(
+            move |time_stamp, message, _| match follower.handle(time_stamp, message) {
+                Some(midi_clock::ClockEvent::Transport(midi_clock::Transport::Playing)) => {
+                    println!("{}", if message[0] == 0xFA { "Start" } else { "Continue" })
+                }
+                Some(midi_clock::ClockEvent::Transport(midi_clock::Transport::Stopped)) => println!("Stop"),
+                Some(midi_clock::ClockEvent::Position(beats)) => println!("Song position {}", beats),
+                Some(midi_clock::ClockEvent::Beat) => {
+                    let (bpm, stats) = match (follower.bpm(), follower.stats()) {
+                        (Some(bpm), Some(stats)) => (bpm, stats),
+                        _ => return,
+                    };
+                    let playing = follower.transport() == midi_clock::Transport::Playing;
+                    let position = if playing {
+                        let (bar, beat) = follower.bar_beat();
+                        format!("bar {:>3} beat {}", bar, beat)
+                    } else {
+                        String::from("stopped")
+                    };
+                    println!(
+                        "{}  {:7.2} BPM  jitter {:.3}ms  interval {:.3}ms (min {:.3}, max {:.3})",
+                        position, bpm, stats.jitter, stats.mean, stats.min, stats.max
+                    );
+                    if let Some(osc_sender) = &osc_sender {
+                        osc_sender.send(String::from("/clock/bpm"), vec![OscType::Float(bpm as f32)]);
+                        if playing {
+                            let (bar, beat) = follower.bar_beat();
+                            osc_sender.send(String::from("/clock/beat"), vec![OscType::Int(bar as i32), OscType::Int(beat as i32)]);
+                        }
+                    }
+                }
+                None => {}
+            },
+            (),
+            running,
+        );
+    }
+}
+
+struct MtcSender {
+    midi_out: midi_io::MidiOut,
+    start: mtc::Timecode,
+}
+
+impl MtcSender {
+    fn new(midi_out_ports: Vec<MidiPort>, start: mtc::Timecode) -> MtcSender {
+        MtcSender {
+            midi_out: midi_io::MidiOut::new(midi_out_ports),
+            start,
+        }
+    }
+
+    /// Sends a full frame to locate, then quarter frames until Ctrl+C. Eight quarter
+    /// frames carry the timecode of the frame the first was sent in.
+    fn run(mut self, running: Arc<AtomicBool>) {
+        let rate = self.start.rate;
+        let quarter_frame_duration = rate.frame_duration() / 4;
+        self.midi_out.send_full(&self.start.full_frame());
+        println!("Sending MTC at {} fps from {}, press Ctrl+C to stop", rate, self.start);
+
+        let begin = Instant::now();
+        let mut quarter_frames: u64 = 0;
+        let mut timecode = self.start;
+        loop {
+            let piece = (quarter_frames % 8) as u8;
+            if piece == 0 {
+                timecode = self.start.add_frames(quarter_frames / 4);
+                if timecode.frames < 2 {
+                    println!("{}", timecode);
+                }
+            }
+            let deadline = begin + quarter_frame_duration.mul_f64(quarter_frames as f64);
+            if !timing::sleep_until(deadline, &running) {
+                break;
+            }
+            self.midi_out.send_full(&MidiMessage::MtcQuarterFrame(timecode.quarter_frame(piece)).encode());
+            quarter_frames += 1;
+        }
+
+        let end = self.start.add_frames(quarter_frames / 4);
+        self.midi_out.send_full(&end.full_frame());
+        println!("Stopped at {}", end);
+    }
+}
+
+struct MtcEcho {
+    midi_in: midi_io::MidiIn,
+    osc_sender: Option<osc_io::OscSender>,
+}
+
+impl MtcEcho {
+    fn new(midi_in: midi_io::MidiIn, osc_host_address: Option<&str>) -> MtcEcho {
+        MtcEcho {
+            midi_in,
+            osc_sender: osc_host_address.map(|address| osc_io::OscSender::new(address.to_string())),
+        }
+    }
+
+    /// Prints every decoded timecode until Ctrl+C, optionally sending it as `/mtc`.
+    fn echo_mtc(self, running: Arc<AtomicBool>) {
+        let mut reader = mtc::MtcReader::new();
+        let mut message_index = 0;
+        let osc_sender = self.osc_sender;
+        self.midi_in.listen_with_interrupt(
+            move |time_stamp, message, _| {
+                if let Some(timecode) = reader.handle(message) {
+                    let kind = if message[0] == 0xF0 { "MTC full frame" } else { "MTC" };
+                    println!("{} {} {} {} fps={}", message_index, time_stamp, kind, timecode, timecode.rate);
+                    message_index += 1;
+                    if let Some(osc_sender) = &osc_sender {
+                        osc_sender.send(String::from("/mtc"), vec![OscType::String(timecode.to_string())]);
+                    }
+                }
+            },
+            (),
+            running,
+        );
+    }
+}
+
+/// How long to wait before the next timetag is due, at most, so Ctrl+C is noticed.
+const SCHEDULE_POLL: Duration = Duration::from_millis(100);
+
+/// Scheduled messages are waited for with `timing::sleep_until` from this long
+/// before they are due, for sends without the jitter of a sleep.
+const SCHEDULE_MARGIN: Duration = Duration::from_millis(5);
+
+/// Forwards OSC messages to MIDI. A bridge carries its own state, so several can
+/// run in one process.
+struct OscToMidi {
+    verbose: bool,
+    osc_path_address: osc_io::AddressPattern,
+    late_policy: osc_schedule::LatePolicy,
+    late_tolerance: Duration,
+}
 
 impl OscToMidi {
     fn new(
-        osc_host_address: &str,
-        midi_out_port_index: usize,
         verbose: bool,
-        osc_path_address: &str,
+        osc_path_address: osc_io::AddressPattern,
+        late_policy: osc_schedule::LatePolicy,
+        late_tolerance: Duration,
     ) -> OscToMidi {
         OscToMidi {
-            osc_host_address: osc_host_address.to_string(),
-            verbose: verbose,
-            midi_out: midi_io::MidiOut::new(midi_out_port_index),
-            osc_path_address: osc_path_address.to_string(),
+            verbose,
+            osc_path_address,
+            late_policy,
+            late_tolerance,
         }
     }
 
-    fn osc_to_midi(osc_host_address: &str,running: Arc<AtomicBool>) {
+    /// Listens until `running` is cleared. Messages are sent to MIDI by an output
+    /// thread, which holds bundle contents back until their timetag.
+    fn osc_to_midi(self, osc_host_address: &str, midi_out: midi_io::MidiOut, running: Arc<AtomicBool>) {
+        let (to_midi, to_send) = channel::<(Instant, Vec<u8>)>();
+        let verbose = self.verbose;
+        let output_running = running.clone();
+        let output = thread::spawn(move || OscToMidi::send_scheduled(midi_out, to_send, verbose, output_running));
+
         let (send, _recv) = channel::<u32>();
-        let callback = || OscToMidi::forward_osc_packet_to_midi;
-        osc_io::OscServer::new(osc_host_address, callback()).listen_with_interrupt(&send,running);
-    }
-
-    fn send_midi_message(message: &[u8]) {
-        INSTANCE
-            .get()
-            .expect("OSC to MIDI not initialized")
-            .lock()
-            .unwrap()
-            .deref_mut()
-            .midi_out
-            .send_full(message);
-    }
-
-    fn osc_path_address() -> String {
-        return INSTANCE
-            .get()
-            .expect("OSC to MIDI not initialized")
-            .lock()
-            .unwrap()
-            .deref_mut()
-            .osc_path_address
-            .to_string();
-    }
-
-    fn verbose() -> bool {
-        return INSTANCE
-            .get()
-            .expect("OSC to MIDI not initialized")
-            .lock()
-            .unwrap()
-            .deref_mut()
-            .verbose;
-    }
-
-    fn forward_osc_packet_to_midi(packet: OscPacket) -> u32 {
-        match packet {
-            OscPacket::Message(msg) => {
-                let path_address = OscToMidi::osc_path_address();
+        osc_io::OscServer::new(osc_host_address, move |packet, from_addr| {
+            self.forward_osc_packet_to_midi(packet, from_addr, &to_midi)
+        })
+        .listen_with_interrupt(&send, running);
+        output.join().unwrap();
+    }
 
-                if msg.addr.as_str() == path_address {
-                    let mut midi_data = vec![];
-                    if OscToMidi::verbose() {
-                        println!("OSC msg received: {:?}", msg);
+    /// Sends messages at their time until `running` is cleared or the bridge stops.
+    fn send_scheduled(
+        mut midi_out: midi_io::MidiOut,
+        to_send: std::sync::mpsc::Receiver<(Instant, Vec<u8>)>,
+        verbose: bool,
+        running: Arc<AtomicBool>,
+    ) {
+        let mut schedule = osc_schedule::Schedule::<Vec<u8>>::new();
+        while running.load(Ordering::SeqCst) {
+            let now = Instant::now();
+            match schedule.next_due() {
+                Some(due) if due <= now + SCHEDULE_MARGIN => {
+                    if !timing::sleep_until(due, &running) {
+                        break;
                     }
-
-                    for osc_arg in msg.args {
-                        match osc_arg.int() {
-                            Some(v) => {
-                                let number: i32 = v;
-                                if number >= 0 && number < 256 {
-                                    midi_data.push(number as u8)
-                                } else {
-                                    if OscToMidi::verbose() {
-                                        println!(
-                                            "Ignored number not fitting in byte: {:?}",
-                                            number
-                                        );
-                                    }
-                                }
-                            }
-                            _ => {
-                                if OscToMidi::verbose() {
-                                    println!("Ignored unsupported OSC type");
-                                }
-                            }
-                        }
+                    while let Some(message) = schedule.pop_due(Instant::now()) {
+                        midi_out.send_full(&message);
+                    }
+                }
+                next_due => {
+                    let wait = next_due.map_or(SCHEDULE_POLL, |due| (due - now - SCHEDULE_MARGIN).min(SCHEDULE_POLL));
+                    match to_send.recv_timeout(wait) {
+                        Ok((due, message)) => schedule.push(due, message),
+                        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
                     }
+                }
+            }
+        }
+        if !schedule.is_empty() && verbose {
+            println!("Dropped {} scheduled messages", schedule.len());
+        }
+    }
 
-                    if midi_data.len() > 0 {
-                        //Max 1000 bytes!
-                        let mut message: [u8; 1000] = [0 as u8; 1000];
-                        let mut index = 0;
-                        for x in &midi_data {
-                            message[index] = *x;
-                            index = index + 1;
+    /// The MIDI bytes of a message: each integer argument is a byte.
+    fn midi_data(&self, msg: rosc::OscMessage) -> Vec<u8> {
+        let mut midi_data = vec![];
+        for osc_arg in msg.args {
+            match osc_arg.int() {
+                Some(v) => {
+                    let number: i32 = v;
+                    if (0..256).contains(&number) {
+                        midi_data.push(number as u8)
+                    } else {
+                        if self.verbose {
+                            println!(
+                                "Ignored number not fitting in byte: {:?}",
+                                number
+                            );
                         }
-                        OscToMidi::send_midi_message(&message[0..midi_data.len()]);
                     }
-                } else {
-                    if OscToMidi::verbose() {
-                        println!("Ignored message on OSC address: {:?}", msg.addr.as_str());
+                }
+                _ => {
+                    if self.verbose {
+                        println!("Ignored unsupported OSC type");
                     }
                 }
             }
+        }
+        midi_data
+    }
 
-            OscPacket::Bundle(bundle) => {
-                println!("OSC Bundle: {:?}", bundle);
+    /// Forwards messages, including those in (nested) bundles. Bundle contents
+    /// are sent at their timetag.
+    fn forward_osc_packet_to_midi(
+        &self,
+        packet: OscPacket,
+        _from_addr: SocketAddr,
+        to_midi: &std::sync::mpsc::Sender<(Instant, Vec<u8>)>,
+    ) -> u32 {
+        for (msg, timetag) in osc_schedule::flatten_packet(packet) {
+            if !self.osc_path_address.routes(&msg.addr) {
+                if self.verbose {
+                    println!("Ignored message on OSC address: {:?}", msg.addr.as_str());
+                }
+                continue;
+            }
+            if self.verbose {
+                println!("OSC msg received: {:?}", msg);
+            }
+
+            let midi_data = self.midi_data(msg);
+            if midi_data.is_empty() {
+                continue;
+            }
+            let due = match osc_schedule::dispatch(timetag.as_ref(), std::time::SystemTime::now(), self.late_policy, self.late_tolerance) {
+                osc_schedule::Dispatch::Now => Instant::now(),
+                osc_schedule::Dispatch::Later(wait) => Instant::now() + wait,
+                osc_schedule::Dispatch::Drop(late) => {
+                    if self.verbose {
+                        println!("Dropped message {:.1}ms late", late.as_secs_f64() * 1000.0);
+                    }
+                    continue;
+                }
+            };
+            // The output thread stopped when Ctrl+C was pressed
+            if to_midi.send((due, midi_data)).is_err() {
+                return 1;
             }
         }
-        return 0;
+        0
     }
 }
 
@@ -212,24 +987,21 @@ struct MidiToOsc {
 impl MidiToOsc {
     fn new(
         osc_host_address: &str,
-        midi_in_port_index: usize,
+        midi_in: midi_io::MidiIn,
         verbose: bool,
         osc_path_address: &str,
     ) -> MidiToOsc {
         MidiToOsc {
             osc_sender: osc_io::OscSender::new(osc_host_address.to_string()),
-            verbose: verbose,
-            midi_in: midi_io::MidiIn::new(midi_in_port_index),
+            verbose,
+            midi_in,
             osc_path_address: osc_path_address.to_string(),
         }
     }
 
-    fn midi_to_osc(self) {
-        let mut message_index = 0;
-
-        self.midi_in.listen(
+    fn midi_to_osc(self, running: Arc<AtomicBool>) {
+        self.midi_in.listen_with_interrupt(
             move |time_stamp, message, _| {
-                message_index = message_index + 1;
                 if self.verbose {
                     println!("{} {:?}", time_stamp, message);
                 }
@@ -241,6 +1013,7 @@ impl MidiToOsc {
                     .send(self.osc_path_address.to_string(), osc_args);
             },
             (),
+            running,
         );
     }
 }
@@ -254,23 +1027,23 @@ struct LuaMidiProcessor {
 
 impl LuaMidiProcessor {
     fn new(
-        midi_in_port_index: usize,
-        midi_out_port_index: usize,
+        midi_in: midi_io::MidiIn,
+        midi_out_ports: Vec<MidiPort>,
         lua_script_path: &str,
         verbose: bool,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         Ok(LuaMidiProcessor {
-            midi_in: midi_io::MidiIn::new(midi_in_port_index),
-            midi_out: midi_io::MidiOut::new(midi_out_port_index),
+            midi_in,
+            midi_out: midi_io::MidiOut::new(midi_out_ports),
             lua_processor: lua_processor::LuaProcessor::new(lua_script_path)?,
             verbose,
         })
     }
 
-    fn process_midi(mut self) {
+    fn process_midi(mut self, running: Arc<AtomicBool>) {
         let mut message_count = 0;
         
-        self.midi_in.listen(
+        self.midi_in.listen_with_interrupt(
             move |_timestamp, message, _| {
                 message_count += 1;
                 
@@ -300,76 +1073,310 @@ impl LuaMidiProcessor {
                 }
             },
             (),
+            running,
         );
     }
 }
 
-fn is_host_with_port(v: &str) -> Result<String, String> {
-    let addr = SocketAddrV4::from_str(v);
-    match addr {
-        Ok(_addr) => return Ok(String::from(v)),
-        Err(_e) => Err(String::from(
-            "Expects a valid IPv4 address with UDP port: xxx.xxx.xxx.xxx:port",
-        )),
-    }
+fn is_host_with_port(v: &str) -> Result<String, String> {
+    resolver::HostPort::from_str(v).map(|_| String::from(v))
+}
+
+/// Accepts `host:port` for UDP, or a URL like `tcp://host:port` to pick the transport.
+fn is_osc_url(v: &str) -> Result<String, String> {
+    osc_io::OscUrl::from_str(v).map(|_| String::from(v))
+}
+
+fn midi_input_arg() -> Arg {
+    Arg::new("midi_input")
+        .default_value("0")
+        .value_parser(PortSpec::from_str)
+        .help("MIDI input port: an index, a (partial) port name or a /regex/. List the devices to see the options.")
+}
+
+fn midi_output_arg() -> Arg {
+    Arg::new("midi_output")
+        .default_value("0")
+        .value_parser(PortSpec::from_str)
+        .help("MIDI output port: an index, a (partial) port name or a /regex/. List the devices to see the options.")
 }
 
-fn osc_send(osc_target_host_address: &str, verbose: bool) {
-    let stdin = io::stdin();
+fn format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .default_value("text")
+        .value_parser(OutputFormat::from_str)
+        .help("output format: text, json (one object per line) or csv")
+}
 
-    let osc_sender = OscSender::new(osc_target_host_address.to_string());
-    for line in stdin.lock().lines() {
-        let line = line.unwrap(); // Handle potential error
-        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+fn virtual_input_arg() -> Arg {
+    Arg::new("virtual_in")
+        .long("virtual-in")
+        .value_name("NAME")
+        .action(ArgAction::Append)
+        .help("Create a virtual MIDI input port with this name. Can be repeated. The MIDI input port is then only used when given explicitly.")
+}
+
+fn virtual_output_arg() -> Arg {
+    Arg::new("virtual_out")
+        .long("virtual-out")
+        .value_name("NAME")
+        .action(ArgAction::Append)
+        .help("Create a virtual MIDI output port with this name. Can be repeated. The MIDI output port is then only used when given explicitly.")
+}
 
-        // do not send empty messages
-        if tokens.len() == 0 {
-            if verbose {
-                print!("Empty message; nothing send\n");
+/// The virtual ports requested with `--virtual-in`/`--virtual-out`, followed by the
+/// resolved port argument unless only virtual ports were requested.
+fn midi_ports(
+    sub_matches: &ArgMatches,
+    port_id: &str,
+    virtual_id: &str,
+    resolve_port: fn(&PortSpec) -> Result<MidiPort, String>,
+) -> Option<Vec<MidiPort>> {
+    let mut ports: Vec<MidiPort> = sub_matches
+        .get_many::<String>(virtual_id)
+        .map(|names| names.map(|name| MidiPort::Virtual(name.to_string())).collect())
+        .unwrap_or_default();
+    if !ports.is_empty() && !cfg!(target_family = "unix") {
+        println!("Virtual MIDI ports are not supported on this platform");
+        return None;
+    }
+    if ports.is_empty() || sub_matches.value_source(port_id) == Some(ValueSource::CommandLine) {
+        let spec = sub_matches
+            .get_one::<PortSpec>(port_id)
+            .expect("MIDI port is required");
+        match resolve_port(spec) {
+            Ok(port) => ports.insert(0, port),
+            Err(e) => {
+                println!("{}", e);
+                return None;
             }
-            continue;
         }
+    }
+    Some(ports)
+}
+
+fn midi_input_ports(sub_matches: &ArgMatches) -> Option<Vec<MidiPort>> {
+    midi_ports(sub_matches, "midi_input", "virtual_in", midi_io::MidiIn::resolve_port)
+}
 
-        // else
+fn midi_filter_args() -> Vec<Arg> {
+    vec![
+        Arg::new("ignore_realtime")
+            .long("ignore-realtime")
+            .num_args(0)
+            .required(false)
+            .help("drop clock, start, stop, continue and active sensing messages"),
+        Arg::new("ignore_sysex")
+            .long("ignore-sysex")
+            .num_args(0)
+            .required(false)
+            .help("drop SysEx messages"),
+        Arg::new("only")
+            .long("only")
+            .value_name("TYPE")
+            .action(ArgAction::Append)
+            .value_parser(midi_filter::parse_message_type)
+            .help("only pass this message type, e.g. noteon, cc or pitchbend. Can be repeated."),
+        Arg::new("skip")
+            .long("skip")
+            .value_name("TYPE")
+            .action(ArgAction::Append)
+            .value_parser(midi_filter::parse_message_type)
+            .help("drop this message type. Can be repeated."),
+        Arg::new("channel")
+            .long("channel")
+            .value_name("CHANNEL")
+            .action(ArgAction::Append)
+            .value_parser(clap::value_parser!(u8).range(1..=16))
+            .help("only pass channel messages on this channel (1-16). Can be repeated."),
+        Arg::new("skip_channel")
+            .long("skip-channel")
+            .value_name("CHANNEL")
+            .action(ArgAction::Append)
+            .value_parser(clap::value_parser!(u8).range(1..=16))
+            .help("drop channel messages on this channel (1-16). Can be repeated."),
+        Arg::new("notes")
+            .long("notes")
+            .value_name("RANGE")
+            .value_parser(midi_filter::parse_note_range)
+            .help("only pass note messages in this range, e.g. C2-C4 or 36-60"),
+    ]
+}
 
-        // check if the first token is a valid osc method (starts with '/')
-        let osc_method = match tokens.get(0) {
-            Some(v) => {
-                if v.starts_with('/') {
-                    v.to_string()
-                } else {
-                    format!("/{}", v)
+fn midi_filter(sub_matches: &ArgMatches) -> midi_filter::MidiFilter {
+    let flag = |id: &str| sub_matches.value_source(id) == Some(ValueSource::CommandLine);
+    let types = |id: &str| sub_matches.get_many::<&'static str>(id).unwrap_or_default().copied().collect();
+    let channels = |id: &str| sub_matches.get_many::<u8>(id).unwrap_or_default().map(|c| c - 1).collect();
+    midi_filter::MidiFilter {
+        ignore_realtime: flag("ignore_realtime"),
+        ignore_sysex: flag("ignore_sysex"),
+        only_types: types("only"),
+        skip_types: types("skip"),
+        only_channels: channels("channel"),
+        skip_channels: channels("skip_channel"),
+        note_range: sub_matches.get_one::<std::ops::RangeInclusive<u8>>("notes").cloned(),
+    }
+}
+
+/// The MIDI input ports of a subcommand with the filter given on the command line.
+fn midi_input(sub_matches: &ArgMatches) -> Option<midi_io::MidiIn> {
+    midi_input_ports(sub_matches).map(|ports| midi_io::MidiIn::new(ports).with_filter(midi_filter(sub_matches)))
+}
+
+fn midi_output_ports(sub_matches: &ArgMatches) -> Option<Vec<MidiPort>> {
+    midi_ports(sub_matches, "midi_output", "virtual_out", midi_io::MidiOut::resolve_port)
+}
+
+/// Sends the message given on the command line `repeat` times, or until Ctrl+C
+/// when None, `interval` apart. Without a message, sends OSC messages read from
+/// STDIN, one per line, see `osc_syntax::parse_message`. Lines between
+/// `#bundle [TIMETAG]` and `#end` are sent as a bundle.
+fn osc_send(osc_target_host_address: &str, message: Option<String>, repeat: Option<u64>, interval: Duration, verbose: bool, running: Arc<AtomicBool>) {
+    let osc_sender = OscSender::new(osc_target_host_address.to_string());
+    let send = |packet: &OscPacket| {
+        osc_sender.send_packet(packet);
+        if verbose {
+            match packet {
+                OscPacket::Message(msg) => {
+                    println!("Sent OSC message {} to {} with args {:?}", msg.addr, osc_target_host_address, msg.args)
                 }
+                OscPacket::Bundle(bundle) => println!("Sent OSC bundle to {}: {:?}", osc_target_host_address, bundle),
             }
-            None => {
-                println!("No OSC method provided");
-                continue;
+        }
+    };
+
+    if let Some(line) = message {
+        let packet = match osc_syntax::parse_message(&line) {
+            Ok(Some(msg)) => OscPacket::Message(msg),
+            Ok(None) => {
+                println!("Empty message; nothing send");
+                return;
+            }
+            Err(e) => {
+                println!("Could not parse '{}': {}", line, e);
+                return;
             }
         };
+        let begin = Instant::now();
+        let mut sent = 0;
+        while repeat.is_none_or(|repeat| sent < repeat) {
+            if !timing::sleep_until(begin + interval.mul_f64(sent as f64), &running) {
+                break;
+            }
+            send(&packet);
+            sent += 1;
+        }
+        return;
+    }
+
+    let stdin = io::stdin();
+    let mut reader = osc_syntax::PacketReader::new();
+    for line in stdin.lock().lines() {
+        let line = line.unwrap(); // Handle potential error
+        match reader.read_line(&line, std::time::SystemTime::now()) {
+            Ok(Some(packet)) => send(&packet),
+            // do not send empty messages, bundles are sent once complete
+            Ok(None) => {
+                if verbose && reader.open_bundles() == 0 && line.trim().is_empty() {
+                    println!("Empty message; nothing send");
+                }
+            }
+            Err(e) => println!("Could not parse '{}': {}", line, e),
+        }
+    }
+    if reader.open_bundles() > 0 {
+        println!("Missing #end, bundle not sent");
+    }
+}
+
+/// Sends MIDI messages read from STDIN, one per line. A line `wait MS` pauses,
+/// lines starting with `#` are comments.
+fn midi_send(midi_out_ports: Vec<MidiPort>, delay: Duration, verbose: bool, running: Arc<AtomicBool>) {
+    let mut midi_out = midi_io::MidiOut::new(midi_out_ports);
+    let stdin = io::stdin();
 
-        // remove the first token
-        tokens.remove(0);
+    for line in stdin.lock().lines() {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
+        let line = line.unwrap();
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
 
-        let mut osc_args: Vec<OscType> = Vec::new();
+        let mut tokens = line.split_whitespace();
+        if let Some("wait" | "sleep") = tokens.next() {
+            match tokens.next().map(|ms| ms.parse::<u64>()) {
+                Some(Ok(ms)) if tokens.next().is_none() => {
+                    timing::sleep_until(Instant::now() + Duration::from_millis(ms), &running);
+                }
+                _ => println!("Usage: wait MILLISECONDS"),
+            }
+            continue;
+        }
 
-        for token in tokens {
-            if let Ok(int) = token.parse::<i32>() {
-                osc_args.push(OscType::Int(int));
-            } else if let Ok(float) = token.parse::<f32>() {
-                osc_args.push(OscType::Float(float));
-            } else {
-                osc_args.push(OscType::String(token.to_string()));
+        match midi_io::parse_message_text(line) {
+            Ok(message) => {
+                midi_out.send_full(&message);
+                if verbose {
+                    match MidiMessage::parse(&message) {
+                        Ok(decoded) => println!("Sent {}", decoded),
+                        Err(_) => println!("Sent {:02X?}", message),
+                    }
+                }
+                if !delay.is_zero() {
+                    timing::sleep_until(Instant::now() + delay, &running);
+                }
             }
+            Err(e) => println!("Could not parse '{}': {}", line, e),
         }
+    }
+}
 
-        osc_sender.send(osc_method.to_string(), osc_args.clone());
+/// Sends the SysEx messages of a `.syx` file. Slow devices can get each message
+/// in chunks of `chunk_size` bytes with a pause in between. Chunks after the
+/// first are raw continuation bytes without F0, which ALSA and CoreMIDI pass on
+/// but other backends, e.g. WinMM, may reject.
+fn sysex_send(
+    midi_out_ports: Vec<MidiPort>,
+    file_path: &str,
+    delay: Duration,
+    chunk_size: Option<usize>,
+    chunk_delay: Duration,
+    running: Arc<AtomicBool>,
+) {
+    let messages = match fs::read(file_path) {
+        Ok(data) => sysex::split_messages(&data),
+        Err(e) => {
+            println!("Could not read {}: {}", file_path, e);
+            return;
+        }
+    };
+    if messages.is_empty() {
+        println!("No SysEx messages found in {}", file_path);
+        return;
+    }
 
-        if verbose {
-            print!(
-                "Sent OSC message to {} with args {:?}\n",
-                osc_target_host_address, osc_args
-            );
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    if chunk_size.is_some() {
+        println!("Chunks after the first are sent without F0, which this platform's MIDI backend may reject");
+    }
+
+    let mut midi_out = midi_io::MidiOut::new(midi_out_ports);
+    for (index, message) in messages.iter().enumerate() {
+        if index > 0 && !timing::sleep_until(Instant::now() + delay, &running) {
+            break;
+        }
+        let chunk_size = chunk_size.unwrap_or(message.len());
+        for (chunk_index, chunk) in message.chunks(chunk_size).enumerate() {
+            if chunk_index > 0 && !timing::sleep_until(Instant::now() + chunk_delay, &running) {
+                return;
+            }
+            midi_out.send_full(chunk);
         }
+        println!("Sent SysEx message {}/{} ({} bytes)", index + 1, messages.len(), message.len());
     }
 }
 
@@ -403,17 +1410,16 @@ fn main() {
                 .help("print debug information verbosely"))
             .arg(Arg::new("host:port")
                 .default_value("127.0.0.1:1234")
-                .help("The host:port to send OSC data to")
+                .help("The host:port to send OSC data to, tcp://host:port or tcp+slip://host:port for TCP")
                 .value_name("host:port")
-                .value_parser(is_host_with_port))
+                .value_parser(is_osc_url))
             .arg(Arg::new("osc_address")
                 .default_value("/midi")
                 .help("The OSC address to send MIDI to.")
                 .value_name("OSC_address"))
-            .arg(Arg::new("midi_input_index")
-                .default_value("0") 
-                .value_parser(clap::value_parser!(usize))
-                .help("MIDI input device index. List the devices to get the correct index."))
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
             .arg(Arg::new("list")
                 .short('l')
                 .num_args(0)
@@ -428,17 +1434,27 @@ fn main() {
                 .help("print debug information verbosely"))
             .arg(Arg::new("host:port")
                 .default_value("127.0.0.1:1234")
-                .help("The host:port to receive OSC data from")
+                .help("The host:port to receive OSC data from, tcp://host:port or tcp+slip://host:port for TCP")
                 .value_name("host:port")
-                .value_parser(is_host_with_port))
+                .value_parser(is_osc_url))
             .arg(Arg::new("osc_address")
                 .default_value("/midi")
-                .help("The OSC address to receive data from.")
-                .value_name("OSC_address"))
-            .arg(Arg::new("midi_output_index")
-                .default_value("0") 
-                .value_parser(clap::value_parser!(usize))
-                .help("MIDI output device index. List the devices to get the correct index."))
+                .help("The OSC address to receive data from. May be a pattern like /midi/* or /{midi,m}.")
+                .value_name("OSC_address")
+                .value_parser(osc_io::AddressPattern::from_str))
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
+            .arg(Arg::new("late")
+                .long("late")
+                .default_value("send")
+                .value_parser(osc_schedule::LatePolicy::from_str)
+                .help("what to do with bundle contents arriving after their timetag: send or drop"))
+            .arg(Arg::new("late_tolerance")
+                .long("late-tolerance")
+                .value_name("MS")
+                .default_value("10")
+                .value_parser(clap::value_parser!(u64))
+                .help("milliseconds bundle contents may be late before --late drop drops them"))
             .arg(Arg::new("list")
                 .short('l')
                 .num_args(0)
@@ -451,31 +1467,130 @@ fn main() {
                 .num_args(0)
                 .required(false)
                 .help("list midi input devices"))
-            .arg(Arg::new("midi_input_index")
-                .default_value("0")
-                .value_parser(clap::value_parser!(usize))
-                .help("MIDI input device index. List the devices to get the correct index."))
+            .arg(Arg::new("raw")
+                .long("raw")
+                .num_args(0)
+                .required(false)
+                .help("print the raw MIDI bytes instead of decoded messages"))
+            .arg(format_arg())
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
             )
         .subcommand(Command::new("osc_echo")
             .about("Print incoming OSC messages.")
             .arg(Arg::new("verbose")
                 .short('v')
                 .help("print verbose information"))
+            .arg(format_arg())
             .arg(Arg::new("host:port")
                 .default_value("0.0.0.0:1234")
-                .help("the host:port to receive OSC data")
-                .value_parser(is_host_with_port))
+                .help("the host:port to receive OSC data, tcp://host:port or tcp+slip://host:port for TCP")
+                .value_parser(is_osc_url))
+            .arg(Arg::new("address")
+                .long("address")
+                .value_name("PATTERN")
+                .value_parser(osc_io::AddressPattern::from_str)
+                .help("only print messages with an address matching this OSC address pattern, e.g. /mixer/ch/*/fader"))
+            .arg(Arg::new("hexdump")
+                .long("hexdump")
+                .num_args(0)
+                .required(false)
+                .conflicts_with("format")
+                .help("print the raw bytes of every packet before its decoded form"))
             )
         .subcommand(Command::new("osc_send")
-            .about("Send OSC messages from STDIN. The first token of each line is the OSC method, the rest are the arguments. Only floats, ints and strings are converted to OSC types.")
+            .about("Send the OSC message given after host:port, or OSC messages from STDIN. The first token of each line is the OSC address, the rest are the arguments: ints, floats and words are inferred, \"quoted strings\" may hold spaces, prefixes like d:1.5, h:123 or b:0A0B set the type, T F N I are true, false, nil and impulse and [1 2 3] is an array. Lines between #bundle [now|+250ms|SECONDS:FRACTION] and #end are sent as one bundle.")
             .arg(Arg::new("verbose")
                 .short('v')
+                .num_args(0)
+                .required(false)
                 .help("print verbose information"))
             .arg(Arg::new("host:port")
                 .default_value("127.0.0.1:1234")
-                .help("the host:port to send OSC data to")
-                .value_parser(is_host_with_port))
+                .help("the host:port to send OSC data to, tcp://host:port or tcp+slip://host:port for TCP")
+                .value_parser(is_osc_url))
+            .arg(Arg::new("message")
+                .num_args(1..)
+                .allow_negative_numbers(true)
+                .value_name("ADDRESS ARGS")
+                .help("the message to send, e.g. /cue/go 3. STDIN is read when missing."))
+            .arg(Arg::new("repeat")
+                .long("repeat")
+                .value_name("N")
+                .requires("message")
+                .value_parser(clap::value_parser!(u64))
+                .help("send the message N times, 0 until Ctrl+C. Defaults to once, or until Ctrl+C with --interval or --rate."))
+            .arg(Arg::new("interval")
+                .long("interval")
+                .value_name("MS")
+                .default_value("1000")
+                .requires("message")
+                .value_parser(clap::value_parser!(f64))
+                .help("milliseconds between repeated messages"))
+            .arg(Arg::new("rate")
+                .long("rate")
+                .value_name("HZ")
+                .requires("message")
+                .conflicts_with("interval")
+                .value_parser(clap::value_parser!(f64))
+                .help("repeated messages per second, instead of --interval"))
             )
+        .subcommand(Command::new("midi_send")
+            .about("Send MIDI messages from STDIN. Each line holds hex bytes (90 3C 64) or a message like noteon 1 C4 100, cc 1 7 127 or sysex F0 .. F7. A line wait MS pauses.")
+            .arg(Arg::new("verbose")
+                .short('v')
+                .num_args(0)
+                .required(false)
+                .help("print each sent message"))
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi output devices"))
+            .arg(Arg::new("delay")
+                .long("delay")
+                .value_name("MS")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u64))
+                .help("milliseconds to wait after each message"))
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
+        )
+        .subcommand(Command::new("osc_latency")
+            .about("Measure OSC round-trip time and loss against a reflector, or run the reflector with --reflect")
+            .arg(Arg::new("host:port")
+                .default_value("127.0.0.1:1234")
+                .help("the reflector to probe, or the address to listen on with --reflect")
+                .value_parser(is_host_with_port))
+            .arg(Arg::new("reflect")
+                .long("reflect")
+                .num_args(0)
+                .required(false)
+                .help("send every received OSC packet back to its sender"))
+            .arg(Arg::new("rate")
+                .long("rate")
+                .value_name("HZ")
+                .default_value("50")
+                .value_parser(clap::value_parser!(f64))
+                .help("probes per second"))
+            .arg(Arg::new("count")
+                .long("count")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("stop after this many probes instead of at Ctrl+C"))
+            .arg(Arg::new("timeout")
+                .long("timeout")
+                .value_name("MS")
+                .default_value("1000")
+                .value_parser(clap::value_parser!(u64))
+                .help("milliseconds after which a probe without reply is lost"))
+            .arg(Arg::new("report")
+                .long("report")
+                .value_name("SECONDS")
+                .default_value("1")
+                .value_parser(clap::value_parser!(f64))
+                .help("seconds between reports"))
+        )
         .subcommand(Command::new("midi_roundtrip_latency")
             .about("Test MIDI roundtrip latency")
             .arg(Arg::new("list")
@@ -483,14 +1598,42 @@ fn main() {
                 .num_args(0)
                 .required(false)
                 .help("list midi input and output devices"))
-            .arg(Arg::new("midi_input_index")
-                .default_value("0")
-                .value_parser(clap::value_parser!(usize))
-                .help("MIDI input device index. List the devices to get the correct index."))
-            .arg(Arg::new("midi_output_index")
-                .default_value("0")
-                .value_parser(clap::value_parser!(usize))
-                .help("MIDI output device index. List the devices to get the correct index."))
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
+            .arg(Arg::new("measure")
+                .long("measure")
+                .num_args(0)
+                .required(false)
+                .help("send probes and measure their round trip instead of echoing messages"))
+            .arg(Arg::new("probe")
+                .long("probe")
+                .default_value("sysex")
+                .value_parser(latency::MidiProbe::from_str)
+                .help("probe message for --measure: sysex or pitchbend (channel 16)"))
+            .arg(Arg::new("count")
+                .long("count")
+                .default_value("1000")
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .help("number of probes to send"))
+            .arg(Arg::new("interval")
+                .long("interval")
+                .value_name("MS")
+                .default_value("10")
+                .value_parser(clap::value_parser!(u64))
+                .help("milliseconds between probes"))
+            .arg(Arg::new("timeout")
+                .long("timeout")
+                .value_name("MS")
+                .default_value("1000")
+                .value_parser(clap::value_parser!(u64))
+                .help("milliseconds to wait for the last probes to return"))
+            .arg(Arg::new("csv")
+                .long("csv")
+                .value_name("FILE")
+                .help("write every sample to a CSV file"))
         )
         .subcommand(Command::new("midi_processor")
             .about("Process MIDI messages through a Lua script")
@@ -504,19 +1647,231 @@ fn main() {
                 .num_args(0)
                 .required(false)
                 .help("list midi input and output devices"))
-            .arg(Arg::new("midi_input_index")
-                .default_value("0")
-                .value_parser(clap::value_parser!(usize))
-                .help("MIDI input device index. List the devices to get the correct index."))
-            .arg(Arg::new("midi_output_index")
-                .default_value("0")
-                .value_parser(clap::value_parser!(usize))
-                .help("MIDI output device index. List the devices to get the correct index."))
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
             .arg(Arg::new("script")
                 .short('s')
                 .long("script")
                 .required_unless_present("list")
                 .help("Path to the Lua script file"))
+        )
+        .subcommand(Command::new("midi_record")
+            .about("Record incoming MIDI to a Standard MIDI File until Ctrl+C is pressed")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi input devices"))
+            .arg(Arg::new("file")
+                .required_unless_present("list")
+                .help("The MIDI file to write"))
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
+            .arg(Arg::new("ppq")
+                .long("ppq")
+                .default_value("480")
+                .value_parser(clap::value_parser!(u16).range(1..0x8000))
+                .help("ticks per quarter note"))
+            .arg(Arg::new("bpm")
+                .long("bpm")
+                .default_value("120")
+                .value_parser(clap::value_parser!(f64))
+                .help("tempo of the file in beats per minute"))
+            .arg(Arg::new("type")
+                .long("type")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u16).range(0..=1))
+                .help("MIDI file type: 0 for a single track, 1 for a tempo track and a track with the recording"))
+            .arg(Arg::new("split_channels")
+                .long("split-channels")
+                .num_args(0)
+                .required(false)
+                .help("write a track per MIDI channel, implies type 1"))
+        )
+        .subcommand(Command::new("sysex_dump")
+            .about("Save incoming SysEx messages to a .syx file")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi input and output devices"))
+            .arg(Arg::new("file")
+                .required_unless_present("list")
+                .help("The .syx file to write"))
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
+            .arg(midi_output_arg()
+                .help("MIDI output port for --request: an index, a (partial) port name or a /regex/."))
+            .arg(virtual_output_arg())
+            .arg(Arg::new("split")
+                .long("split")
+                .num_args(0)
+                .required(false)
+                .help("write each message to its own numbered file, e.g. dump_001.syx"))
+            .arg(Arg::new("count")
+                .long("count")
+                .value_parser(clap::value_parser!(usize))
+                .help("stop after this many messages"))
+            .arg(Arg::new("request")
+                .long("request")
+                .value_name("MESSAGE")
+                .help("message to send first, e.g. a dump request: hex bytes or sysex F0 .. F7"))
+        )
+        .subcommand(Command::new("sysex_send")
+            .about("Send the SysEx messages of a .syx file")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi output devices"))
+            .arg(Arg::new("file")
+                .required_unless_present("list")
+                .help("The .syx file to send"))
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
+            .arg(Arg::new("delay")
+                .long("delay")
+                .value_name("MS")
+                .default_value("50")
+                .value_parser(clap::value_parser!(u64))
+                .help("milliseconds between messages"))
+            .arg(Arg::new("chunk_size")
+                .long("chunk-size")
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .help("send messages in chunks of this many bytes instead of whole. Only ALSA and CoreMIDI are known to pass on the chunks after the first."))
+            .arg(Arg::new("chunk_delay")
+                .long("chunk-delay")
+                .value_name("MS")
+                .default_value("10")
+                .value_parser(clap::value_parser!(u64))
+                .help("milliseconds between chunks of a message"))
+        )
+        .subcommand(Command::new("midi_clock")
+            .about("Send MIDI clock, controllable over OSC with /tempo, /start, /stop, /continue and /position")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi output devices"))
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
+            .arg(Arg::new("bpm")
+                .long("bpm")
+                .default_value("120")
+                .value_parser(clap::value_parser!(f64))
+                .help("tempo in beats per minute"))
+            .arg(Arg::new("osc")
+                .long("osc")
+                .value_name("host:port")
+                .value_parser(is_osc_url)
+                .help("listen for OSC clock controls on this address, e.g. /tempo 128.0"))
+            .arg(Arg::new("position")
+                .long("position")
+                .value_name("BEATS")
+                .value_parser(clap::value_parser!(u16).range(0..=0x3FFF))
+                .help("song position in sixteenth notes to continue from instead of starting"))
+            .arg(Arg::new("no_start")
+                .long("no-start")
+                .num_args(0)
+                .required(false)
+                .help("only send clock ticks, without start message"))
+        )
+        .subcommand(Command::new("midi_clock_monitor")
+            .about("Follow incoming MIDI clock: tempo, transport, position and tick jitter")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi input devices"))
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
+            .arg(Arg::new("beats_per_bar")
+                .long("beats-per-bar")
+                .default_value("4")
+                .value_parser(clap::value_parser!(u32).range(1..=64))
+                .help("beats per bar for the bar and beat position"))
+            .arg(Arg::new("osc")
+                .long("osc")
+                .value_name("host:port")
+                .value_parser(is_osc_url)
+                .help("send the tempo as /clock/bpm and the bar and beat as /clock/beat to this address"))
+        )
+        .subcommand(Command::new("mtc_send")
+            .about("Send MIDI Time Code from a start time until Ctrl+C is pressed")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi output devices"))
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
+            .arg(Arg::new("fps")
+                .long("fps")
+                .default_value("25")
+                .value_parser(mtc::FrameRate::from_str)
+                .help("frame rate: 24, 25, 29.97df or 30"))
+            .arg(Arg::new("start")
+                .long("start")
+                .value_name("hh:mm:ss:ff")
+                .default_value("00:00:00:00")
+                .help("timecode to start from"))
+        )
+        .subcommand(Command::new("mtc_echo")
+            .about("Print incoming MIDI Time Code as SMPTE timecode")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi input devices"))
+            .arg(midi_input_arg())
+            .arg(virtual_input_arg())
+            .args(midi_filter_args())
+            .arg(Arg::new("osc")
+                .long("osc")
+                .value_name("host:port")
+                .value_parser(is_osc_url)
+                .help("send each timecode as /mtc \"hh:mm:ss:ff\" to this address"))
+        )
+        .subcommand(Command::new("midi_play")
+            .about("Play a Standard MIDI File to a MIDI output port")
+            .arg(Arg::new("list")
+                .short('l')
+                .num_args(0)
+                .required(false)
+                .help("list midi output devices"))
+            .arg(Arg::new("file")
+                .required_unless_present("list")
+                .help("The MIDI file to play"))
+            .arg(midi_output_arg())
+            .arg(virtual_output_arg())
+            .arg(Arg::new("speed")
+                .long("speed")
+                .default_value("1")
+                .value_parser(clap::value_parser!(f64))
+                .help("playback speed factor, 2 plays twice as fast"))
+            .arg(Arg::new("loop")
+                .long("loop")
+                .num_args(0)
+                .required(false)
+                .help("repeat the file until Ctrl+C is pressed"))
+            .arg(Arg::new("start")
+                .long("start")
+                .default_value("0")
+                .value_parser(clap::value_parser!(f64))
+                .help("start offset in seconds"))
+            .arg(Arg::new("mute")
+                .long("mute")
+                .value_name("CHANNEL")
+                .action(ArgAction::Append)
+                .value_parser(clap::value_parser!(u8).range(1..=16))
+                .help("do not play this MIDI channel (1-16), can be repeated"))
         ).get_matches();
     
     let running = setup_interrupt_handler();
@@ -526,12 +1881,11 @@ fn main() {
             println! {"Listing MIDI input devices:"}
             midi_io::MidiIn::list_midi_input_ports();
         } else {
-            let midi_input_index: usize = *sub_matches
-                .get_one("midi_input_index")
-                .expect("`midi_input_index` is required");
-            //let midi_input_index = usize::from_str(sub_matches.value_of("midi_input_index").unwrap()).unwrap();
-            if midi_io::MidiIn::check_midi_input_port_index(midi_input_index) {
-                MidiEcho::new(midi_input_index).echo_midi();
+            if let Some(midi_in) = midi_input(sub_matches) {
+                let raw =
+                    sub_matches.value_source("raw") == Some(clap::parser::ValueSource::CommandLine);
+                let format = *sub_matches.get_one::<OutputFormat>("format").unwrap();
+                MidiEcho::new(midi_in, raw, format).echo_midi(running.clone());
             }
         }
     }
@@ -540,29 +1894,58 @@ fn main() {
         let osc_target_host_address = sub_matches.get_one::<String>("host:port").unwrap();
         let verbose =
             sub_matches.value_source("verbose") == Some(clap::parser::ValueSource::CommandLine);
-        osc_send(osc_target_host_address, verbose);
+        let message = sub_matches
+            .get_many::<String>("message")
+            .map(|args| osc_syntax::join_args(&args.cloned().collect::<Vec<_>>()));
+        let timed = |id: &str| sub_matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine);
+        let seconds = match sub_matches.get_one::<f64>("rate") {
+            Some(rate) => 1.0 / rate,
+            None => sub_matches.get_one::<f64>("interval").unwrap() / 1000.0,
+        };
+        let interval = match Duration::try_from_secs_f64(seconds) {
+            Ok(interval) if seconds.is_finite() => interval,
+            _ => {
+                println!("The rate should be more than 0 and the interval 0 or more");
+                return;
+            }
+        };
+        let repeat = match sub_matches.get_one::<u64>("repeat") {
+            Some(0) => None,
+            Some(repeat) => Some(*repeat),
+            None if timed("interval") || timed("rate") => None,
+            None => Some(1),
+        };
+        osc_send(osc_target_host_address, message, repeat, interval, verbose, running.clone());
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("midi_send") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiOut::list_midi_output_ports();
+        } else if let Some(midi_output_ports) = midi_output_ports(sub_matches) {
+            let delay = Duration::from_millis(*sub_matches.get_one::<u64>("delay").unwrap());
+            let verbose =
+                sub_matches.value_source("verbose") == Some(clap::parser::ValueSource::CommandLine);
+            midi_send(midi_output_ports, delay, verbose, running.clone());
+        }
     }
 
     if let Some(sub_matches) = matches.subcommand_matches("midi_to_osc") {
         if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
             midi_io::MidiIn::list_midi_input_ports();
         } else {
-            let midi_input_index: usize = *sub_matches
-                .get_one("midi_input_index")
-                .expect("`midi_input_index` is required");
             let verbose =
                 sub_matches.value_source("verbose") == Some(clap::parser::ValueSource::CommandLine);
             let osc_target_host_address = sub_matches.get_one::<String>("host:port").unwrap();
             let osc_target_osc_address = sub_matches.get_one::<String>("osc_address").unwrap();
 
-            if midi_io::MidiIn::check_midi_input_port_index(midi_input_index) {
+            if let Some(midi_in) = midi_input(sub_matches) {
                 MidiToOsc::new(
                     osc_target_host_address,
-                    midi_input_index,
+                    midi_in,
                     verbose,
                     osc_target_osc_address,
                 )
-                .midi_to_osc();
+                .midi_to_osc(running.clone());
             }
         }
     }
@@ -571,34 +1954,25 @@ fn main() {
         if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
             midi_io::MidiOut::list_midi_output_ports();
         } else {
-            let midi_output_index: usize = *sub_matches
-                .get_one("midi_output_index")
-                .expect("`midi_output_index` is required");
             let verbose =
                 sub_matches.value_source("verbose") == Some(clap::parser::ValueSource::CommandLine);
             let osc_host_address = sub_matches.get_one::<String>("host:port").unwrap();
-            let osc_method_address = sub_matches.get_one::<String>("osc_address").unwrap();
+            let osc_method_address = sub_matches.get_one::<osc_io::AddressPattern>("osc_address").unwrap();
 
-            if midi_io::MidiOut::check_midi_output_port_index(midi_output_index) {
+            if let Some(midi_output_ports) = midi_output_ports(sub_matches) {
                 let osc_to_midi = OscToMidi::new(
-                    osc_host_address,
-                    midi_output_index,
                     verbose,
-                    osc_method_address,
+                    osc_method_address.clone(),
+                    *sub_matches.get_one::<osc_schedule::LatePolicy>("late").unwrap(),
+                    Duration::from_millis(*sub_matches.get_one::<u64>("late_tolerance").unwrap()),
                 );
-                INSTANCE.set(Mutex::new(osc_to_midi)).unwrap();
 
                 // Register mDNS service to indicate that this is an OSC receiver
                 let mut mdns = mdns_service_manager::MdnsService::new().unwrap();
 
                 // Register a simple OSC service
-                let port = osc_host_address
-                    .split(':')
-                    .last()
-                    .unwrap_or("8080")
-                    .parse::<u16>()
-                    .unwrap_or(8080);
-                mdns.register("mot-osc-listener", "_osc._udp", port)
+                let url = osc_io::OscUrl::from_str(osc_host_address).unwrap();
+                mdns.register("mot-osc-listener", url.mdns_service_type(), url.address.port)
                     .unwrap();
 
                 let mdns_running = running.clone();
@@ -606,25 +1980,24 @@ fn main() {
                     mdns.run_with_interrupt(mdns_running).unwrap();
                 });
 
-                OscToMidi::osc_to_midi(osc_host_address,running.clone());
+                osc_to_midi.osc_to_midi(osc_host_address, midi_io::MidiOut::new(midi_output_ports), running.clone());
             }
         }
     }
 
     if let Some(sub_matches) = matches.subcommand_matches("osc_echo") {
         let addr = sub_matches.get_one::<String>("host:port").unwrap();
-        
+        let mut echo = osc_io::OscEcho::new(
+            *sub_matches.get_one::<OutputFormat>("format").unwrap(),
+            sub_matches.get_one::<osc_io::AddressPattern>("address").cloned(),
+        );
+
         // Register mDNS service to indicate that this is an OSC receiver
         let mut mdns = mdns_service_manager::MdnsService::new().unwrap();
 
         // Register a simple OSC service
-        let port = addr
-            .split(':')
-            .last()
-            .unwrap_or("8080")
-            .parse::<u16>()
-            .unwrap_or(8080);
-        mdns.register("mot-osc-echo", "_osc._udp", port)
+        let url = osc_io::OscUrl::from_str(addr).unwrap();
+        mdns.register("mot-osc-echo", url.mdns_service_type(), url.address.port)
             .unwrap();
 
         let mdns_running = running.clone();
@@ -633,7 +2006,40 @@ fn main() {
         });
 
         let (send, _recv) = channel::<u32>();
-        osc_io::OscServer::new(addr, osc_io::OscServer::echo_osc_packet).listen_with_interrupt(&send, running);
+        let mut server = osc_io::OscServer::new(addr, move |packet, from_addr| echo.echo_osc_packet(packet, from_addr));
+        if sub_matches.value_source("hexdump") == Some(clap::parser::ValueSource::CommandLine) {
+            server = server.with_raw_handler(|bytes, from_addr| {
+                print!("{} bytes from {}:\n{}", bytes.len(), from_addr, output_format::hex_dump(bytes));
+            });
+        }
+        server.listen_with_interrupt(&send, running.clone());
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("osc_latency") {
+        let osc_host_address = sub_matches.get_one::<String>("host:port").unwrap();
+        if sub_matches.value_source("reflect") == Some(clap::parser::ValueSource::CommandLine) {
+            let (send, _recv) = channel::<u32>();
+            let mut reflector = osc_io::OscReflector::new();
+            osc_io::OscServer::new_replying(osc_host_address, move |packet, from_addr, replies| {
+                reflector.reflect_osc_packet(packet, from_addr, replies)
+            })
+                .listen_with_interrupt(&send, running.clone());
+        } else {
+            let rate = *sub_matches.get_one::<f64>("rate").unwrap();
+            let report = *sub_matches.get_one::<f64>("report").unwrap();
+            if rate <= 0.0 || report <= 0.0 {
+                println!("The rate and report interval should be more than 0");
+                return;
+            }
+            OscLatencyProbe::new(
+                osc_host_address,
+                rate,
+                sub_matches.get_one::<u64>("count").copied(),
+                Duration::from_millis(*sub_matches.get_one::<u64>("timeout").unwrap()),
+                Duration::from_secs_f64(report),
+            )
+            .measure(running.clone());
+        }
     }
 
     if let Some(sub_matches) = matches.subcommand_matches("midi_roundtrip_latency") {
@@ -641,17 +2047,27 @@ fn main() {
             midi_io::MidiIn::list_midi_input_ports();
             midi_io::MidiOut::list_midi_output_ports();
         } else {
-            let midi_input_index: usize = *sub_matches
-                .get_one("midi_input_index")
-                .expect("`midi_input_index` is required");
-            let midi_output_index: usize = *sub_matches
-                .get_one("midi_output_index")
-                .expect("`midi_output_index` is required");
             println! {"MIDI roundtrip latency application."}
-            if midi_io::MidiIn::check_midi_input_port_index(midi_input_index)
-                && midi_io::MidiOut::check_midi_output_port_index(midi_output_index)
+            let measure = sub_matches.value_source("measure") == Some(clap::parser::ValueSource::CommandLine);
+            let options = ProbeOptions {
+                probe: *sub_matches.get_one::<latency::MidiProbe>("probe").unwrap(),
+                count: *sub_matches.get_one::<u32>("count").unwrap(),
+                interval: Duration::from_millis(*sub_matches.get_one::<u64>("interval").unwrap()),
+                timeout: Duration::from_millis(*sub_matches.get_one::<u64>("timeout").unwrap()),
+                csv_path: sub_matches.get_one::<String>("csv").cloned(),
+            };
+            if options.count > options.probe.sequence_count() {
+                println!("At most {} probes can be told apart with this probe", options.probe.sequence_count());
+                return;
+            }
+            if let (Some(midi_in), Some(midi_output_ports)) =
+                (midi_input(sub_matches), midi_output_ports(sub_matches))
             {
-                MidiRoundTrip::new(midi_input_index, midi_output_index).respond_to_midi();
+                if measure {
+                    MidiLatencyProbe::new(midi_in, midi_output_ports, options).measure(running.clone());
+                } else {
+                    MidiRoundTrip::new(midi_in, midi_output_ports).respond_to_midi(running.clone());
+                }
             }
         }
     }
@@ -661,12 +2077,6 @@ fn main() {
             midi_io::MidiIn::list_midi_input_ports();
             midi_io::MidiOut::list_midi_output_ports();
         } else {
-            let midi_input_index: usize = *sub_matches
-                .get_one("midi_input_index")
-                .expect("`midi_input_index` is required");
-            let midi_output_index: usize = *sub_matches
-                .get_one("midi_output_index")
-                .expect("`midi_output_index` is required");
             let script_path = sub_matches.get_one::<String>("script").unwrap();
             let verbose = 
                 sub_matches.value_source("verbose") == Some(clap::parser::ValueSource::CommandLine);
@@ -674,13 +2084,13 @@ fn main() {
             println!("MIDI Processor");
             println!("Loading Lua script: {}", script_path);
             
-            if midi_io::MidiIn::check_midi_input_port_index(midi_input_index)
-                && midi_io::MidiOut::check_midi_output_port_index(midi_output_index)
+            if let (Some(midi_in), Some(midi_output_ports)) =
+                (midi_input(sub_matches), midi_output_ports(sub_matches))
             {
-                match LuaMidiProcessor::new(midi_input_index, midi_output_index, script_path, verbose) {
+                match LuaMidiProcessor::new(midi_in, midi_output_ports, script_path, verbose) {
                     Ok(processor) => {
                         println!("Lua script loaded successfully. Processing MIDI...");
-                        processor.process_midi();
+                        processor.process_midi(running.clone());
                     }
                     Err(e) => {
                         eprintln!("Error loading Lua script: {}", e);
@@ -689,4 +2099,158 @@ fn main() {
             }
         }
     }
+
+    if let Some(sub_matches) = matches.subcommand_matches("midi_record") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiIn::list_midi_input_ports();
+        } else {
+            let file_path = sub_matches.get_one::<String>("file").unwrap();
+            let bpm = *sub_matches.get_one::<f64>("bpm").unwrap();
+            if !(smf::MIN_BPM..=smf::MAX_BPM).contains(&bpm) {
+                println!("The tempo of a MIDI file should be between {:.2} and {} BPM", smf::MIN_BPM, smf::MAX_BPM);
+                return;
+            }
+            let options = smf::RecordOptions {
+                ppq: *sub_matches.get_one::<u16>("ppq").unwrap(),
+                bpm,
+                format: *sub_matches.get_one::<u16>("type").unwrap(),
+                split_channels: sub_matches.value_source("split_channels")
+                    == Some(clap::parser::ValueSource::CommandLine),
+            };
+            if let Some(midi_in) = midi_input(sub_matches) {
+                MidiRecorder::new(midi_in, file_path, options).record(running.clone());
+            }
+        }
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("midi_play") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiOut::list_midi_output_ports();
+        } else {
+            let file_path = sub_matches.get_one::<String>("file").unwrap();
+            let speed = *sub_matches.get_one::<f64>("speed").unwrap();
+            let start = *sub_matches.get_one::<f64>("start").unwrap();
+            if speed <= 0.0 {
+                println!("The speed should be more than 0");
+                return;
+            }
+            if start < 0.0 {
+                println!("The start offset should not be negative");
+                return;
+            }
+            let smf = match fs::read(file_path).map_err(|e| e.to_string()).and_then(|bytes| smf::Smf::parse(&bytes)) {
+                Ok(smf) => smf,
+                Err(e) => {
+                    println!("Could not read {}: {}", file_path, e);
+                    return;
+                }
+            };
+            let muted: Vec<u8> = sub_matches.get_many::<u8>("mute").unwrap_or_default().copied().collect();
+            let looping = sub_matches.value_source("loop") == Some(clap::parser::ValueSource::CommandLine);
+            if let Some(midi_output_ports) = midi_output_ports(sub_matches) {
+                MidiPlayer::new(midi_output_ports, &smf, &muted, speed, looping, start).play(running.clone());
+            }
+        }
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("sysex_dump") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiIn::list_midi_input_ports();
+            midi_io::MidiOut::list_midi_output_ports();
+        } else {
+            let file_path = sub_matches.get_one::<String>("file").unwrap();
+            let split = sub_matches.value_source("split") == Some(clap::parser::ValueSource::CommandLine);
+            let count = sub_matches.get_one::<usize>("count").copied();
+            let request = match sub_matches.get_one::<String>("request").map(|r| midi_io::parse_message_text(r)) {
+                Some(Ok(request)) => Some(request),
+                Some(Err(e)) => {
+                    println!("Invalid request message: {}", e);
+                    return;
+                }
+                None => None,
+            };
+            if let Some(midi_in) = midi_input(sub_matches) {
+                let mut dumper = SysExDumper::new(midi_in, file_path, split, count);
+                if let Some(request) = request {
+                    match midi_output_ports(sub_matches) {
+                        Some(midi_output_ports) => dumper = dumper.with_request(midi_output_ports, request),
+                        None => return,
+                    }
+                }
+                dumper.dump(running.clone());
+            }
+        }
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("sysex_send") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiOut::list_midi_output_ports();
+        } else if let Some(midi_output_ports) = midi_output_ports(sub_matches) {
+            let file_path = sub_matches.get_one::<String>("file").unwrap();
+            let delay = Duration::from_millis(*sub_matches.get_one::<u64>("delay").unwrap());
+            let chunk_size = sub_matches.get_one::<u64>("chunk_size").map(|size| *size as usize);
+            let chunk_delay = Duration::from_millis(*sub_matches.get_one::<u64>("chunk_delay").unwrap());
+            sysex_send(midi_output_ports, file_path, delay, chunk_size, chunk_delay, running.clone());
+        }
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("midi_clock") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiOut::list_midi_output_ports();
+        } else {
+            let bpm = *sub_matches.get_one::<f64>("bpm").unwrap();
+            if bpm <= 0.0 || bpm > 1000.0 {
+                println!("The tempo should be more than 0 and at most 1000 BPM");
+                return;
+            }
+            let mut commands = Vec::new();
+            if sub_matches.value_source("no_start") != Some(clap::parser::ValueSource::CommandLine) {
+                match sub_matches.get_one::<u16>("position") {
+                    Some(position) => commands.extend([ClockCommand::Position(*position), ClockCommand::Continue]),
+                    None => commands.push(ClockCommand::Start),
+                }
+            }
+            let osc_address = sub_matches.get_one::<String>("osc").cloned();
+            if let Some(midi_output_ports) = midi_output_ports(sub_matches) {
+                MidiClock::new(midi_output_ports, bpm).run(commands, osc_address, running.clone());
+            }
+        }
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("midi_clock_monitor") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiIn::list_midi_input_ports();
+        } else if let Some(midi_in) = midi_input(sub_matches) {
+            let beats_per_bar = *sub_matches.get_one::<u32>("beats_per_bar").unwrap();
+            let osc_address = sub_matches.get_one::<String>("osc").map(|a| a.as_str());
+            ClockMonitor::new(midi_in, beats_per_bar, osc_address).monitor(running.clone());
+        }
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("mtc_send") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiOut::list_midi_output_ports();
+        } else {
+            let rate = *sub_matches.get_one::<mtc::FrameRate>("fps").unwrap();
+            let start = match mtc::Timecode::parse(sub_matches.get_one::<String>("start").unwrap(), rate) {
+                Ok(start) => start,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            if let Some(midi_output_ports) = midi_output_ports(sub_matches) {
+                MtcSender::new(midi_output_ports, start).run(running.clone());
+            }
+        }
+    }
+
+    if let Some(sub_matches) = matches.subcommand_matches("mtc_echo") {
+        if sub_matches.value_source("list") == Some(clap::parser::ValueSource::CommandLine) {
+            midi_io::MidiIn::list_midi_input_ports();
+        } else if let Some(midi_in) = midi_input(sub_matches) {
+            let osc_address = sub_matches.get_one::<String>("osc").map(|a| a.as_str());
+            MtcEcho::new(midi_in, osc_address).echo_mtc(running.clone());
+        }
+    }
 }
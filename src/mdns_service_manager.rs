@@ -1,4 +1,5 @@
-use mdns_sd::{ServiceDaemon, ServiceInfo, DaemonEvent};
+use mdns_sd::{DaemonEvent, HostnameResolutionEvent, ScopedIp, ServiceDaemon, ServiceInfo};
+use std::net::{SocketAddr, SocketAddrV6};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
@@ -129,6 +130,37 @@ impl MdnsService {
 
 }
 
+/// Resolve a `.local.` hostname over mDNS
+///
+/// Waits up to `timeout` for the first answer and returns its addresses with
+/// `port`. Link-local IPv6 addresses keep the interface they were seen on.
+pub fn resolve_hostname(
+    hostname: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error>> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.resolve_hostname(hostname, Some(timeout.as_millis() as u64))?;
+
+    let mut addresses = Vec::new();
+    while let Ok(event) = receiver.recv_timeout(timeout) {
+        match event {
+            HostnameResolutionEvent::AddressesFound(_, found) => {
+                addresses.extend(found.iter().map(|ip| match ip {
+                    ScopedIp::V6(v6) => SocketAddr::V6(SocketAddrV6::new(*v6.addr(), port, 0, v6.scope_id().index)),
+                    other => SocketAddr::new(other.to_ip_addr(), port),
+                }));
+                break;
+            }
+            HostnameResolutionEvent::SearchTimeout(_) | HostnameResolutionEvent::SearchStopped(_) => break,
+            _ => continue,
+        }
+    }
+
+    let _ = daemon.shutdown();
+    Ok(addresses)
+}
+
 impl Drop for MdnsService {
     fn drop(&mut self) {
         // Attempt to unregister on drop
@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use rosc::{OscMessage, OscType};
+
+/// MIDI clock messages per quarter note.
+pub const TICKS_PER_QUARTER: u32 = 24;
+
+pub fn tick_period(bpm: f64) -> Duration {
+    Duration::from_secs_f64(60.0 / (bpm * TICKS_PER_QUARTER as f64))
+}
+
+/// Times clock ticks relative to an anchor instead of the previous tick, so late
+/// wake-ups do not add up to drift. A tempo change moves the anchor to the next
+/// tick.
+pub struct TickSchedule {
+    anchor: Instant,
+    anchor_tick: u64,
+    tick: u64,
+    bpm: f64,
+}
+
+impl TickSchedule {
+    pub fn new(start: Instant, bpm: f64) -> TickSchedule {
+        TickSchedule { anchor: start, anchor_tick: 0, tick: 0, bpm }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    /// When the next tick is due.
+    pub fn next(&self) -> Instant {
+        self.anchor + tick_period(self.bpm).mul_f64((self.tick - self.anchor_tick) as f64)
+    }
+
+    /// Marks the next tick as sent.
+    pub fn advance(&mut self) {
+        self.tick += 1;
+    }
+
+    pub fn set_bpm(&mut self, bpm: f64) {
+        self.anchor = self.next();
+        self.anchor_tick = self.tick;
+        self.bpm = bpm;
+    }
+
+    /// Restarts the schedule at `now`, e.g. after the process was suspended, instead
+    /// of sending a burst of ticks to catch up.
+    pub fn resync(&mut self, now: Instant) {
+        self.anchor = now;
+        self.anchor_tick = self.tick;
+    }
+}
+
+/// Controls of a running clock, received over OSC.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClockCommand {
+    Tempo(f64),
+    Start,
+    Stop,
+    Continue,
+    /// Song position in MIDI beats (sixteenth notes).
+    Position(u16),
+}
+
+impl ClockCommand {
+    /// Reads `/tempo 128.0`, `/start`, `/stop`, `/continue` or `/position 16`.
+    pub fn from_osc(msg: &OscMessage) -> Result<ClockCommand, String> {
+        let number = || match msg.args.first() {
+            Some(OscType::Float(v)) => Ok(*v as f64),
+            Some(OscType::Double(v)) => Ok(*v),
+            Some(OscType::Int(v)) => Ok(*v as f64),
+            Some(OscType::Long(v)) => Ok(*v as f64),
+            _ => Err(format!("{} expects a number", msg.addr)),
+        };
+        match msg.addr.as_str() {
+            "/tempo" => {
+                let bpm = number()?;
+                if bpm > 0.0 && bpm <= 1000.0 {
+                    Ok(ClockCommand::Tempo(bpm))
+                } else {
+                    Err(format!("Tempo {} out of range, expects more than 0 up to 1000 BPM", bpm))
+                }
+            }
+            "/start" => Ok(ClockCommand::Start),
+            "/stop" => Ok(ClockCommand::Stop),
+            "/continue" => Ok(ClockCommand::Continue),
+            "/position" => {
+                let position = number()?;
+                if (0.0..=0x3FFF as f64).contains(&position) {
+                    Ok(ClockCommand::Position(position as u16))
+                } else {
+                    Err(format!("Song position {} out of range 0-16383", position))
+                }
+            }
+            other => Err(format!("Unknown clock control {}", other)),
+        }
+    }
+}
+
+/// Number of tick intervals the tempo and jitter are computed over: two beats.
+const FOLLOW_WINDOW: usize = 2 * TICKS_PER_QUARTER as usize;
+
+/// Song position ticks per MIDI beat (a sixteenth note).
+const TICKS_PER_MIDI_BEAT: u64 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transport {
+    Stopped,
+    Playing,
+}
+
+/// What a received message changed for a `ClockFollower`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockEvent {
+    Transport(Transport),
+    /// Song position pointer in MIDI beats.
+    Position(u16),
+    /// A quarter note passed: on the beat while playing, every 24 ticks otherwise.
+    Beat,
+}
+
+/// Statistics of the recent intervals between clock ticks, in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TickStats {
+    pub mean: f64,
+    /// Standard deviation of the intervals.
+    pub jitter: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Follows incoming clock and transport messages: tempo, position and the
+/// regularity of the ticks.
+pub struct ClockFollower {
+    beats_per_bar: u32,
+    transport: Transport,
+    /// Ticks since the start of the song.
+    position: u64,
+    ticks: u64,
+    last_tick: Option<u64>,
+    intervals: VecDeque<u64>,
+}
+
+impl ClockFollower {
+    pub fn new(beats_per_bar: u32) -> ClockFollower {
+        ClockFollower {
+            beats_per_bar,
+            transport: Transport::Stopped,
+            position: 0,
+            ticks: 0,
+            last_tick: None,
+            intervals: VecDeque::with_capacity(FOLLOW_WINDOW),
+        }
+    }
+
+    /// Handles a message received at `time_stamp` microseconds.
+    pub fn handle(&mut self, time_stamp: u64, message: &[u8]) -> Option<ClockEvent> {
+        match message.first()? {
+            0xF8 => self.tick(time_stamp),
+            0xFA => {
+                self.position = 0;
+                self.transport = Transport::Playing;
+                Some(ClockEvent::Transport(Transport::Playing))
+            }
+            0xFB => {
+                self.transport = Transport::Playing;
+                Some(ClockEvent::Transport(Transport::Playing))
+            }
+            0xFC => {
+                self.transport = Transport::Stopped;
+                Some(ClockEvent::Transport(Transport::Stopped))
+            }
+            0xF2 if message.len() == 3 => {
+                let beats = message[1] as u16 | (message[2] as u16) << 7;
+                self.position = beats as u64 * TICKS_PER_MIDI_BEAT;
+                Some(ClockEvent::Position(beats))
+            }
+            _ => None,
+        }
+    }
+
+    fn tick(&mut self, time_stamp: u64) -> Option<ClockEvent> {
+        // Time stamps restart when a port reconnects
+        match self.last_tick {
+            Some(last) if time_stamp >= last => {
+                if self.intervals.len() == FOLLOW_WINDOW {
+                    self.intervals.pop_front();
+                }
+                self.intervals.push_back(time_stamp - last);
+            }
+            _ => self.intervals.clear(),
+        }
+        self.last_tick = Some(time_stamp);
+
+        let ticks = if self.transport == Transport::Playing {
+            self.position += 1;
+            self.position - 1
+        } else {
+            self.ticks += 1;
+            self.ticks - 1
+        };
+        if ticks % TICKS_PER_QUARTER as u64 == 0 {
+            Some(ClockEvent::Beat)
+        } else {
+            None
+        }
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// The one based bar and beat the song is at.
+    pub fn bar_beat(&self) -> (u64, u32) {
+        let beat = self.position.saturating_sub(1) / TICKS_PER_QUARTER as u64;
+        (beat / self.beats_per_bar as u64 + 1, (beat % self.beats_per_bar as u64) as u32 + 1)
+    }
+
+    pub fn stats(&self) -> Option<TickStats> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+        let count = self.intervals.len() as f64;
+        let millis = || self.intervals.iter().map(|i| *i as f64 / 1000.0);
+        let mean = millis().sum::<f64>() / count;
+        let variance = millis().map(|i| (i - mean) * (i - mean)).sum::<f64>() / count;
+        Some(TickStats {
+            mean,
+            jitter: variance.sqrt(),
+            min: millis().fold(f64::INFINITY, f64::min),
+            max: millis().fold(0.0, f64::max),
+        })
+    }
+
+    /// The tempo averaged over the recent ticks.
+    pub fn bpm(&self) -> Option<f64> {
+        self.stats()
+            .filter(|stats| stats.mean > 0.0)
+            .map(|stats| 60_000.0 / (stats.mean * TICKS_PER_QUARTER as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Instant, b: Instant) {
+        assert!(a.max(b) - a.min(b) < Duration::from_micros(1), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_schedule_follows_tempo_changes() {
+        let start = Instant::now();
+        let mut schedule = TickSchedule::new(start, 120.0);
+        assert_eq!(schedule.next(), start);
+        for _ in 0..24 {
+            schedule.advance();
+        }
+        assert_close(schedule.next(), start + Duration::from_millis(500));
+
+        schedule.set_bpm(60.0);
+        for _ in 0..24 {
+            schedule.advance();
+        }
+        assert_close(schedule.next(), start + Duration::from_millis(1500));
+
+        let later = start + Duration::from_secs(10);
+        schedule.resync(later);
+        assert_eq!(schedule.next(), later);
+    }
+
+    #[test]
+    fn test_commands_from_osc() {
+        let msg = |addr: &str, args: Vec<OscType>| OscMessage { addr: addr.to_string(), args };
+        assert_eq!(ClockCommand::from_osc(&msg("/tempo", vec![OscType::Float(128.0)])), Ok(ClockCommand::Tempo(128.0)));
+        assert_eq!(ClockCommand::from_osc(&msg("/tempo", vec![OscType::Int(90)])), Ok(ClockCommand::Tempo(90.0)));
+        assert_eq!(ClockCommand::from_osc(&msg("/start", vec![])), Ok(ClockCommand::Start));
+        assert_eq!(ClockCommand::from_osc(&msg("/position", vec![OscType::Int(16)])), Ok(ClockCommand::Position(16)));
+        assert!(ClockCommand::from_osc(&msg("/tempo", vec![])).is_err());
+        assert!(ClockCommand::from_osc(&msg("/tempo", vec![OscType::Float(-1.0)])).is_err());
+        assert!(ClockCommand::from_osc(&msg("/bpm", vec![])).is_err());
+    }
+
+    #[test]
+    fn test_follower_tempo_and_position() {
+        let mut follower = ClockFollower::new(4);
+        assert_eq!(follower.handle(0, &[0xF8]), Some(ClockEvent::Beat));
+        assert_eq!(follower.bpm(), None);
+        assert_eq!(follower.handle(10, &[0xFA]), Some(ClockEvent::Transport(Transport::Playing)));
+
+        // 120 BPM is a tick every 20833us, alternate 200us early and late
+        let mut time = 0;
+        let mut beats = 0;
+        for tick in 0..(24 * 5) {
+            time += if tick % 2 == 0 { 20_633 } else { 21_033 };
+            if follower.handle(time, &[0xF8]) == Some(ClockEvent::Beat) {
+                beats += 1;
+            }
+        }
+        assert_eq!(beats, 5);
+        assert_eq!(follower.bar_beat(), (2, 1));
+        assert!((follower.bpm().unwrap() - 120.0).abs() < 0.01);
+        let stats = follower.stats().unwrap();
+        assert!((stats.jitter - 0.2).abs() < 0.001);
+        assert_eq!((stats.min, stats.max), (20.633, 21.033));
+
+        assert_eq!(follower.handle(time, &[0xFC]), Some(ClockEvent::Transport(Transport::Stopped)));
+        assert_eq!(follower.handle(time, &[0xF2, 32, 0]), Some(ClockEvent::Position(32)));
+        assert_eq!(follower.handle(time, &[0xFB]), Some(ClockEvent::Transport(Transport::Playing)));
+        follower.handle(time + 20_833, &[0xF8]);
+        assert_eq!(follower.bar_beat(), (3, 1));
+    }
+}
@@ -0,0 +1,170 @@
+use std::ops::RangeInclusive;
+
+use crate::midi_io::{parse_note, MidiMessage};
+
+/// Decides which incoming MIDI messages reach an application. Channel filters
+/// only apply to channel messages and the note range only to note messages, other
+/// messages pass them. The default filter passes everything.
+#[derive(Clone, Debug, Default)]
+pub struct MidiFilter {
+    /// Drops clock, start, stop, continue, active sensing and reset.
+    pub ignore_realtime: bool,
+    pub ignore_sysex: bool,
+    /// Message types to pass, by `MidiMessage::name()`. Empty passes all types.
+    pub only_types: Vec<&'static str>,
+    pub skip_types: Vec<&'static str>,
+    /// Zero based channels to pass. Empty passes all channels.
+    pub only_channels: Vec<u8>,
+    pub skip_channels: Vec<u8>,
+    pub note_range: Option<RangeInclusive<u8>>,
+}
+
+impl MidiFilter {
+    pub fn accepts(&self, message: &[u8]) -> bool {
+        let status = match message.first() {
+            Some(status) => *status,
+            None => return false,
+        };
+        if self.ignore_realtime && status >= 0xF8 {
+            return false;
+        }
+        if self.ignore_sysex && (status == 0xF0 || status == 0xF7) {
+            return false;
+        }
+
+        let decoded = MidiMessage::parse(message).ok();
+        let name = decoded.as_ref().map(|m| m.name());
+        if !self.only_types.is_empty() && !name.is_some_and(|n| self.only_types.contains(&n)) {
+            return false;
+        }
+        if name.is_some_and(|n| self.skip_types.contains(&n)) {
+            return false;
+        }
+
+        if let Some(channel) = decoded.as_ref().and_then(|m| m.channel()) {
+            if !self.only_channels.is_empty() && !self.only_channels.contains(&channel) {
+                return false;
+            }
+            if self.skip_channels.contains(&channel) {
+                return false;
+            }
+        }
+
+        match (&self.note_range, &decoded) {
+            (
+                Some(range),
+                Some(MidiMessage::NoteOn { note, .. } | MidiMessage::NoteOff { note, .. } | MidiMessage::PolyAftertouch { note, .. }),
+            ) => range.contains(note),
+            _ => true,
+        }
+    }
+}
+
+/// Parses a message type as used by `midi_send`, e.g. `noteon`, `cc` or `clock`,
+/// into the name given by `MidiMessage::name()`.
+pub fn parse_message_type(text: &str) -> Result<&'static str, String> {
+    let name = match text.to_lowercase().as_str() {
+        "noteon" | "on" => "NoteOn",
+        "noteoff" | "off" => "NoteOff",
+        "polyaftertouch" | "polyat" => "PolyAftertouch",
+        "cc" | "controlchange" => "CC",
+        "pc" | "program" | "programchange" => "ProgramChange",
+        "aftertouch" | "at" => "Aftertouch",
+        "pitchbend" | "pb" => "PitchBend",
+        "sysex" => "SysEx",
+        "mtc" | "mtcquarterframe" => "MTCQuarterFrame",
+        "songposition" | "spp" => "SongPosition",
+        "songselect" => "SongSelect",
+        "tunerequest" => "TuneRequest",
+        "clock" => "Clock",
+        "start" => "Start",
+        "continue" => "Continue",
+        "stop" => "Stop",
+        "activesensing" => "ActiveSensing",
+        "reset" => "Reset",
+        _ => return Err(format!("Unknown message type '{}', expects e.g. noteon, cc, pitchbend, sysex or clock", text)),
+    };
+    Ok(name)
+}
+
+/// Parses a note range like `C2-C4` or `36-60`; a single note is a range of one.
+pub fn parse_note_range(text: &str) -> Result<RangeInclusive<u8>, String> {
+    if let Ok(note) = parse_note(text) {
+        return Ok(note..=note);
+    }
+    // The separator is the dash which splits the text in two notes; other dashes
+    // belong to octave -1
+    for (index, _) in text.match_indices('-') {
+        if let (Ok(low), Ok(high)) = (parse_note(&text[..index]), parse_note(&text[index + 1..])) {
+            if low > high {
+                return Err(format!("Empty note range '{}', the lowest note comes first", text));
+            }
+            return Ok(low..=high);
+        }
+    }
+    Err(format!("Invalid note range '{}', expects e.g. C2-C4 or 36-60", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_filter_passes_everything() {
+        let filter = MidiFilter::default();
+        assert!(filter.accepts(&[0xF8]));
+        assert!(filter.accepts(&[0xF0, 0x7E, 0xF7]));
+        assert!(filter.accepts(&[0x90, 60, 100]));
+        assert!(!filter.accepts(&[]));
+    }
+
+    #[test]
+    fn test_realtime_and_sysex() {
+        let filter = MidiFilter { ignore_realtime: true, ignore_sysex: true, ..Default::default() };
+        assert!(!filter.accepts(&[0xF8]));
+        assert!(!filter.accepts(&[0xFE]));
+        assert!(!filter.accepts(&[0xF0, 0x7E, 0xF7]));
+        assert!(filter.accepts(&[0xF2, 0, 0]));
+        assert!(filter.accepts(&[0xB0, 7, 100]));
+    }
+
+    #[test]
+    fn test_types_and_channels() {
+        let filter = MidiFilter {
+            only_types: vec!["NoteOn", "NoteOff", "Clock"],
+            skip_channels: vec![9],
+            ..Default::default()
+        };
+        assert!(filter.accepts(&[0x90, 60, 100]));
+        assert!(!filter.accepts(&[0x99, 36, 100]));
+        assert!(!filter.accepts(&[0xB0, 7, 100]));
+        assert!(filter.accepts(&[0xF8]));
+
+        let filter = MidiFilter { only_channels: vec![0], skip_types: vec!["CC"], ..Default::default() };
+        assert!(filter.accepts(&[0x90, 60, 100]));
+        assert!(!filter.accepts(&[0x91, 60, 100]));
+        assert!(!filter.accepts(&[0xB0, 7, 100]));
+        assert!(filter.accepts(&[0xF8]));
+    }
+
+    #[test]
+    fn test_note_range() {
+        let filter = MidiFilter { note_range: Some(parse_note_range("C4-C5").unwrap()), ..Default::default() };
+        assert!(filter.accepts(&[0x90, 60, 100]));
+        assert!(filter.accepts(&[0x80, 72, 0]));
+        assert!(!filter.accepts(&[0x90, 59, 100]));
+        assert!(filter.accepts(&[0xB0, 1, 0]));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_message_type("CC"), Ok("CC"));
+        assert_eq!(parse_message_type("noteon"), Ok("NoteOn"));
+        assert!(parse_message_type("note").is_err());
+        assert_eq!(parse_note_range("36-60"), Ok(36..=60));
+        assert_eq!(parse_note_range("C-1-C0"), Ok(0..=12));
+        assert_eq!(parse_note_range("A4"), Ok(69..=69));
+        assert!(parse_note_range("C5-C4").is_err());
+        assert!(parse_note_range("low-high").is_err());
+    }
+}
@@ -1,165 +1,960 @@
-use midir::{MidiOutput,MidiInput, Ignore,MidiOutputConnection};
+use midir::{MidiOutput,MidiInput, Ignore,MidiInputConnection,MidiOutputConnection};
 
 #[cfg(target_family = "unix")]
 use midir::os::unix::{VirtualInput,VirtualOutput};
 
-use std::io::stdin;
+use regex::Regex;
+
+use crate::midi_filter::MidiFilter;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Port index which selects the virtual mot port instead of a device port.
+pub const VIRTUAL_PORT_INDEX: usize = 6666;
+
+/// How often the port list is checked for ports that disappear or come back.
+const HOT_PLUG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often a listening port checks whether it should stop.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Selects a MIDI port by index, by name or by a `/regex/` on the port name.
+/// A name first matches exactly, then as a case-insensitive fragment.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PortSpec {
+	Index(usize),
+	Name(String),
+	Regex(String),
+}
+
+impl FromStr for PortSpec {
+	type Err = String;
+
+	fn from_str(spec: &str) -> Result<PortSpec, String> {
+		if let Ok(index) = spec.parse::<usize>() {
+			return Ok(PortSpec::Index(index));
+		}
+		if spec.len() > 1 && spec.starts_with('/') && spec.ends_with('/') {
+			let pattern = &spec[1..spec.len() - 1];
+			if let Err(e) = Regex::new(pattern) {
+				return Err(format!("Invalid port regex {}: {}", spec, e));
+			}
+			return Ok(PortSpec::Regex(pattern.to_string()));
+		}
+		if spec.is_empty() {
+			return Err(String::from("Expects a MIDI port index, name or /regex/"));
+		}
+		Ok(PortSpec::Name(spec.to_string()))
+	}
+}
+
+impl fmt::Display for PortSpec {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			PortSpec::Index(index) => write!(f, "{}", index),
+			PortSpec::Name(name) => write!(f, "\"{}\"", name),
+			PortSpec::Regex(pattern) => write!(f, "/{}/", pattern),
+		}
+	}
+}
+
+impl PortSpec {
+	/// Returns the index of the single port in `port_names` selected by this spec.
+	/// The error lists the candidates when no port or several ports match.
+	pub fn resolve(&self, port_names: &[String]) -> Result<usize, String> {
+		let matches: Vec<usize> = match self {
+			PortSpec::Index(index) => {
+				if *index < port_names.len() {
+					return Ok(*index);
+				}
+				Vec::new()
+			}
+			PortSpec::Name(name) => {
+				let exact: Vec<usize> = (0..port_names.len())
+					.filter(|&i| port_names[i] == *name)
+					.collect();
+				if exact.is_empty() {
+					let fragment = name.to_lowercase();
+					(0..port_names.len())
+						.filter(|&i| port_names[i].to_lowercase().contains(&fragment))
+						.collect()
+				} else {
+					exact
+				}
+			}
+			PortSpec::Regex(pattern) => {
+				let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+				(0..port_names.len())
+					.filter(|&i| regex.is_match(&port_names[i]))
+					.collect()
+			}
+		};
+
+		match matches.len() {
+			1 => Ok(matches[0]),
+			0 => Err(format!(
+				"No port matches {}. Choose one of the following:\n{}",
+				self,
+				list_ports(port_names, 0..port_names.len())
+			)),
+			_ => Err(format!(
+				"{} matches several ports, be more specific:\n{}",
+				self,
+				list_ports(port_names, matches.into_iter())
+			)),
+		}
+	}
+}
+
+fn list_ports(port_names: &[String], indexes: impl Iterator<Item = usize>) -> String {
+	let lines: Vec<String> = indexes
+		.map(|i| format!("{}: {}", i, port_names[i]))
+		.collect();
+	if lines.is_empty() {
+		String::from("(no ports available)")
+	} else {
+		lines.join("\n")
+	}
+}
+
+/// Name of the virtual port selected with the backwards compatible index 6666.
+pub const DEFAULT_VIRTUAL_PORT_NAME: &str = "mot virtual port";
+
+/// A resolved MIDI port: a device port identified by its name, or a virtual port
+/// created by mot with the given name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiPort {
+	Named(String),
+	Virtual(String),
+}
+
+/// ALSA appends the client and port number to a port name. Those numbers change
+/// when a device is plugged in again, so they are ignored to recognize a port.
+fn port_identity(name: &str) -> &str {
+	match name.rsplit_once(' ') {
+		Some((base, ids)) => match ids.split_once(':') {
+			Some((client, port))
+				if !client.is_empty() && !port.is_empty()
+					&& client.chars().chain(port.chars()).all(|c| c.is_ascii_digit()) => base,
+			_ => name,
+		},
+		None => name,
+	}
+}
+
+/// Finds the port with the given name, or else a port which is the same device
+/// plugged in again.
+fn find_port(port_names: &[String], name: &str) -> Option<usize> {
+	port_names.iter().position(|n| n == name).or_else(|| {
+		port_names.iter().position(|n| port_identity(n) == port_identity(name))
+	})
+}
+
+fn midi_input() -> MidiInput {
+	let mut midi_in = MidiInput::new("midir reading input").unwrap();
+	midi_in.ignore(Ignore::None);
+	midi_in
+}
+
+fn input_port_names(midi_in: &MidiInput) -> Vec<String> {
+	midi_in.ports().iter()
+		.map(|p| midi_in.port_name(p).unwrap_or_default())
+		.collect()
+}
+
+fn output_port_names(midi_out: &MidiOutput) -> Vec<String> {
+	midi_out.ports().iter()
+		.map(|p| midi_out.port_name(p).unwrap_or_default())
+		.collect()
+}
 
 pub struct MidiIn {
-	midi_in: MidiInput,
-	midi_in_index: usize,
+	ports: Vec<MidiPort>,
+	filter: MidiFilter,
 }
 
 impl MidiIn {
 
-	pub fn new(midi_in_index: usize) -> MidiIn {
-
-		let mut midi_in = MidiInput::new("midir reading input").unwrap();
-		midi_in.ignore(Ignore::None);
-
-        MidiIn{
-        	midi_in: midi_in,
-            midi_in_index: midi_in_index,
-        }
-    }
-
-    #[cfg(target_family = "unix")]
-    pub fn listen<F,T: 'static +  Send>(self, callback: F,data: T,)  where F: FnMut(u64, &[u8], &mut T) + Send + 'static {
-    	
-    	let _con = match self.midi_in_index {
-
-    		6666 => {
-    			println!("#Receiving MIDI from virtual mot port");
-    			self.midi_in.create_virtual("mot virtual port", callback,data)
-    		}
-    		_ => {
-    			let in_ports = self.midi_in.ports();
-    			let in_port = &in_ports[self.midi_in_index];
-    			println!("#Receiving MIDI from {:?} ",self.midi_in.port_name(&in_port).unwrap());
-                self.midi_in.connect(&in_port, "midir-read-input", callback,data)
-            }
-    	};
-    	
-    	let mut input = String::new();
-        input.clear();
-        stdin().read_line(&mut input).unwrap(); // wait for next enter key press
-        println!("Closing MIDI port");
-    }
-
-    #[cfg(not(target_family = "unix"))]
-    pub fn listen<F,T: 'static +  Send>(self, callback: F,data: T,)  where F: FnMut(u64, &[u8], &mut T) + Send + 'static {
-		let in_ports = self.midi_in.ports();
-		let in_port = &in_ports[self.midi_in_index];
-		println!("#Receiving MIDI from {:?} ",self.midi_in.port_name(&in_port).unwrap());
-		let _con = self.midi_in.connect(&in_port, "midir-read-input", callback,data);
-
-		let mut input = String::new();
-		input.clear();
-		stdin().read_line(&mut input).unwrap(); // wait for next enter key press
-		println!("Closing MIDI port");
-    }
+	/// Listens to all given ports; their messages arrive at the same callback.
+	pub fn new(ports: Vec<MidiPort>) -> MidiIn {
+		MidiIn{
+			ports,
+			filter: MidiFilter::default(),
+		}
+	}
 
+	/// Only passes the messages accepted by `filter` to the callback.
+	pub fn with_filter(mut self, filter: MidiFilter) -> MidiIn {
+		self.filter = filter;
+		self
+	}
+
+	/// Resolves a port spec against the currently available MIDI input ports.
+	pub fn resolve_port(spec: &PortSpec) -> Result<MidiPort, String> {
+		if cfg!(target_family = "unix") && *spec == PortSpec::Index(VIRTUAL_PORT_INDEX) {
+			return Ok(MidiPort::Virtual(DEFAULT_VIRTUAL_PORT_NAME.to_string()));
+		}
+		let names = input_port_names(&midi_input());
+		match spec.resolve(&names) {
+			Ok(index) => Ok(MidiPort::Named(names[index].clone())),
+			Err(e) => Err(format!("Invalid MIDI input port. {}", e)),
+		}
+	}
+
+	/// Calls `callback` for each incoming message until `running` is cleared, e.g. by
+	/// Ctrl+C. A device port which disappears is reconnected as soon as it comes back.
+	pub fn listen_with_interrupt<F,T: 'static +  Send>(self, mut callback: F,data: T, running: Arc<AtomicBool>)  where F: FnMut(u64, &[u8], &mut T) + Send + 'static {
+		self.listen_with_source(
+			move |_source, time_stamp, message, data| callback(time_stamp, message, data),
+			data,
+			running,
+		);
+	}
+
+	/// Like `listen_with_interrupt`, but also passes the name of the port each
+	/// message arrived on.
+	pub fn listen_with_source<F,T: 'static +  Send>(self, mut callback: F,data: T, running: Arc<AtomicBool>)  where F: FnMut(&str, u64, &[u8], &mut T) + Send + 'static {
+		let filter = self.filter;
+		let callback = move |source: &str, time_stamp: u64, message: &[u8], data: &mut T| {
+			if filter.accepts(message) {
+				callback(source, time_stamp, message, data);
+			}
+		};
+		// The callback and its data outlive a single connection, so they are shared
+		// with every connection made to every port.
+		let shared = Arc::new(Mutex::new((callback, data)));
+		let watcher = midi_input();
+		let mut inputs: Vec<InputPort> = self.ports.into_iter()
+			.map(|port| InputPort{ port, state: InputState::Waiting(midi_input()), connected_before: false })
+			.collect();
+		let mut last_check: Option<Instant> = None;
+
+		while running.load(Ordering::SeqCst) {
+			if last_check.is_some_and(|t| t.elapsed() < HOT_PLUG_POLL_INTERVAL) {
+				thread::sleep(INTERRUPT_POLL_INTERVAL);
+				continue;
+			}
+			last_check = Some(Instant::now());
+
+			let watched_names = input_port_names(&watcher);
+			for input in inputs.iter_mut() {
+				input.check(&watched_names, &shared);
+			}
+		}
+
+		for input in inputs {
+			if let InputState::Connected(connection, _) = input.state {
+				connection.close();
+			}
+		}
+		eprintln!("Closing MIDI port");
+	}
 
 	pub fn list_midi_input_ports(){
-	    let mut midi_in = MidiInput::new("midir reading input").unwrap();
-	    midi_in.ignore(Ignore::None);
-	    
-	    let in_ports = midi_in.ports();
-	    println!("Available MIDI input ports:");
-	    for (i, p) in in_ports.iter().enumerate() {
-	        println!("{}: {}", i, midi_in.port_name(p).unwrap());
-	    }
-	    if cfg!(target_family = "unix"){
-			println!("6666: Virtual mot input port");
-		}
-	    
-	    println!("");
-	    
-	}
-
-	pub fn check_midi_input_port_index(midi_in_index: usize) -> bool{
-		let mut midi_in = MidiInput::new("midir reading input").unwrap();
-	    midi_in.ignore(Ignore::None);
-	    
-	    if midi_in_index >= midi_in.ports().len() && midi_in_index != 6666 {
-	    	println!("{} is an invalid MIDI input port index.", midi_in_index);
-	    	println!("Choose one of the following:");
-	    	MidiIn::list_midi_input_ports();
-	    }
-	    midi_in_index < midi_in.ports().len() || midi_in_index == 6666
+		let in_ports = input_port_names(&midi_input());
+		println!("Available MIDI input ports:");
+		for (i, p) in in_ports.iter().enumerate() {
+			println!("{}: {}", i, p);
+		}
+		if cfg!(target_family = "unix"){
+			println!("{}: Virtual mot input port", VIRTUAL_PORT_INDEX);
+		}
+
+		println!();
 	}
 }
 
-pub struct MidiOut {
-	conn_out: MidiOutputConnection
+enum InputState {
+	Connected(MidiInputConnection<()>, String),
+	Waiting(MidiInput),
+	Closed,
 }
 
-impl MidiOut {
+struct InputPort {
+	port: MidiPort,
+	state: InputState,
+	connected_before: bool,
+}
 
-	#[cfg(target_family = "unix")]
-	pub fn new(midi_out_index: usize) -> MidiOut {
+impl InputPort {
+	/// Connects the port when it is (again) available and notices when it disappears.
+	fn check<F,T: 'static + Send>(&mut self, watched_names: &[String], shared: &Arc<Mutex<(F, T)>>) where F: FnMut(&str, u64, &[u8], &mut T) + Send + 'static {
+		let shared = shared.clone();
+		let source = match &self.port {
+			MidiPort::Named(name) | MidiPort::Virtual(name) => name.clone(),
+		};
+		let forward = move |time_stamp: u64, message: &[u8], _: &mut ()| {
+			let mut guard = shared.lock().unwrap();
+			let (callback, data) = &mut *guard;
+			callback(&source, time_stamp, message, data);
+		};
 
+		self.state = match (std::mem::replace(&mut self.state, InputState::Closed), &self.port) {
+			(InputState::Connected(connection, connected_name), MidiPort::Named(name)) => {
+				if watched_names.contains(&connected_name) {
+					InputState::Connected(connection, connected_name)
+				} else {
+					eprintln!("#MIDI input port {:?} disconnected, waiting for it to come back", name);
+					InputState::Waiting(connection.close().0)
+				}
+			}
+			#[cfg(target_family = "unix")]
+			(InputState::Waiting(midi_in), MidiPort::Virtual(name)) => {
+				match midi_in.create_virtual(name, forward, ()) {
+					Ok(connection) => {
+						eprintln!("#Receiving MIDI from virtual port {:?}", name);
+						InputState::Connected(connection, name.clone())
+					}
+					Err(e) => {
+						eprintln!("Could not create virtual MIDI port {:?}: {}", name, e);
+						InputState::Closed
+					}
+				}
+			}
+			#[cfg(not(target_family = "unix"))]
+			(InputState::Waiting(_), MidiPort::Virtual(_)) => {
+				eprintln!("Virtual MIDI ports are not supported on this platform");
+				InputState::Closed
+			}
+			(InputState::Waiting(midi_in), MidiPort::Named(name)) => {
+				let names = input_port_names(&midi_in);
+				match find_port(&names, name) {
+					Some(index) => {
+						let in_port = midi_in.ports()[index].clone();
+						match midi_in.connect(&in_port, "midir-read-input", forward, ()) {
+							Ok(connection) => {
+								if self.connected_before {
+									eprintln!("#MIDI input port {:?} reconnected", names[index]);
+								} else {
+									eprintln!("#Receiving MIDI from {:?} ", names[index]);
+								}
+								self.connected_before = true;
+								InputState::Connected(connection, names[index].clone())
+							}
+							Err(e) => {
+								eprintln!("Could not connect to MIDI input port {:?}: {}", names[index], e);
+								InputState::Waiting(e.into_inner())
+							}
+						}
+					}
+					None => InputState::Waiting(midi_in),
+				}
+			}
+			(state, _) => state,
+		};
+	}
+}
+
+struct OutputPort {
+	port: MidiPort,
+	conn_out: Option<MidiOutputConnection>,
+	midi_out: Option<MidiOutput>,
+	connected_name: String,
+}
+
+impl OutputPort {
+	fn new(port: MidiPort) -> OutputPort {
 		let midi_out = MidiOutput::new("midir reading output").unwrap();
-		let out_connection = match midi_out_index {
-    		6666 => {
-    			println!("#Sending MIDI to virtual mot port");
-    			midi_out.create_virtual("mot virtual port").expect("Could not create virtual port")
-    		},
-    		_ => {
-    			let out_ports = midi_out.ports();
-                let out_port = &out_ports[midi_out_index];
-                midi_out.connect(out_port, "mot-out").expect("Could not connect to port")
-            }
-    	};
-
-        MidiOut{
-            conn_out:  out_connection
-        }
-    }
-
-    #[cfg(not(target_family = "unix"))]
-	pub fn new(midi_out_index: usize) -> MidiOut {
-		let midi_out = MidiOutput::new("midir reading output").unwrap();
-    	let out_ports = midi_out.ports();
-        let out_port = &out_ports[midi_out_index];
-        let out_connection = midi_out.connect(out_port, "mot-out").expect("Could not connect to port");
+		match &port {
+			#[cfg(target_family = "unix")]
+			MidiPort::Virtual(name) => {
+				eprintln!("#Sending MIDI to virtual port {:?}", name);
+				let connection = midi_out.create_virtual(name).expect("Could not create virtual port");
+				OutputPort{
+					conn_out: Some(connection),
+					midi_out: None,
+					connected_name: name.clone(),
+					port,
+				}
+			},
+			#[cfg(not(target_family = "unix"))]
+			MidiPort::Virtual(_) => panic!("Virtual MIDI ports are not supported on this platform"),
+			MidiPort::Named(_) => {
+				let mut output = OutputPort{
+					port,
+					conn_out: None,
+					midi_out: Some(midi_out),
+					connected_name: String::new(),
+				};
+				output.connect();
+				output
+			}
+		}
+	}
+
+	fn connect(&mut self) {
+		let (Some(midi_out), MidiPort::Named(name)) = (self.midi_out.take(), &self.port) else {
+			return;
+		};
+		let names = output_port_names(&midi_out);
+		let Some(index) = find_port(&names, name) else {
+			self.midi_out = Some(midi_out);
+			return;
+		};
+		let out_port = midi_out.ports()[index].clone();
+		match midi_out.connect(&out_port, "mot-out") {
+			Ok(connection) => {
+				if !self.connected_name.is_empty() {
+					eprintln!("#MIDI output port {:?} reconnected", name);
+				}
+				self.conn_out = Some(connection);
+				self.connected_name = names[index].clone();
+			}
+			Err(e) => {
+				eprintln!("Could not connect to MIDI output port {:?}: {}", name, e);
+				self.midi_out = Some(e.into_inner());
+			}
+		}
+	}
+
+	/// Notices a device port which disappeared or came back.
+	fn check(&mut self, watched_names: &[String]) {
+		if let MidiPort::Virtual(_) = self.port {
+			return;
+		}
+		if self.conn_out.is_some() {
+			if !watched_names.contains(&self.connected_name) {
+				eprintln!("#MIDI output port {:?} disconnected, dropping messages until it comes back", self.connected_name);
+				self.midi_out = self.conn_out.take().map(|connection| connection.close());
+			}
+		} else {
+			self.connect();
+		}
+	}
+}
 
-        MidiOut{
-            conn_out:  out_connection
-        }
-    }
+pub struct MidiOut {
+	/// Shared with the thread which watches for ports that disappear or come back.
+	outputs: Arc<Mutex<Vec<OutputPort>>>,
+}
 
-    //pub fn send(&mut self, midi_cmd: u8,  data1:u8 , data2: u8){
-    //	self.conn_out.send(&[midi_cmd, data1, data2]).unwrap();
-    //}
+impl MidiOut {
 
-    pub fn send_full(&mut self, message: &[u8]){
-    	self.conn_out.send(message).unwrap();
-    }
+	/// Sends every message to all given ports. Device ports are checked in the
+	/// background, also while nothing is sent, until the `MidiOut` is dropped.
+	pub fn new(ports: Vec<MidiPort>) -> MidiOut {
+		let outputs = Arc::new(Mutex::new(ports.into_iter().map(OutputPort::new).collect()));
+		let watched = Arc::downgrade(&outputs);
+		thread::spawn(move || watch_outputs(watched));
+		MidiOut{ outputs }
+	}
 
+	/// Resolves a port spec against the currently available MIDI output ports.
+	pub fn resolve_port(spec: &PortSpec) -> Result<MidiPort, String> {
+		if cfg!(target_family = "unix") && *spec == PortSpec::Index(VIRTUAL_PORT_INDEX) {
+			return Ok(MidiPort::Virtual(DEFAULT_VIRTUAL_PORT_NAME.to_string()));
+		}
+		let names = output_port_names(&MidiOutput::new("midir reading output").unwrap());
+		match spec.resolve(&names) {
+			Ok(index) => Ok(MidiPort::Named(names[index].clone())),
+			Err(e) => Err(format!("Invalid MIDI output port. {}", e)),
+		}
+	}
+
+	//pub fn send(&mut self, midi_cmd: u8,  data1:u8 , data2: u8){
+	//	self.conn_out.send(&[midi_cmd, data1, data2]).unwrap();
+	//}
+
+	pub fn send_full(&mut self, message: &[u8]){
+		for output in self.outputs.lock().unwrap().iter_mut() {
+			if let Some(connection) = &mut output.conn_out {
+				if let Err(e) = connection.send(message) {
+					eprintln!("Could not send MIDI message {:?}: {}", message, e);
+				}
+			}
+		}
+	}
 
 	pub fn list_midi_output_ports(){
-	    let midi_out = MidiOutput::new("midir reading input").unwrap();
-	    
-	    let out_ports = midi_out.ports();
-	    println!("Available MIDI output ports:");
-	    for (i, p) in out_ports.iter().enumerate() {
-	        println!("{}: {}", i, midi_out.port_name(p).unwrap());
-	    }
-	    if cfg!(target_family = "unix") {
-			println!("6666: Virtual mot ouput port");
-		}
-	    println!("");
-	}
-
-	pub fn check_midi_output_port_index(midi_out_index: usize) -> bool{
-		let midi_out = MidiOutput::new("midir reading input").unwrap();
-	    
-	    if midi_out_index >= midi_out.ports().len()  && midi_out_index != 6666 {
-	    	println!("{} is an invalid MIDI output port index.", midi_out_index);
-	    	println!("Choose one of the following:");
-	    	MidiOut::list_midi_output_ports();
-	    }
-	    midi_out_index < midi_out.ports().len() || midi_out_index == 6666
-	}
-}
\ No newline at end of file
+		let out_ports = output_port_names(&MidiOutput::new("midir reading output").unwrap());
+		println!("Available MIDI output ports:");
+		for (i, p) in out_ports.iter().enumerate() {
+			println!("{}: {}", i, p);
+		}
+		if cfg!(target_family = "unix") {
+			println!("{}: Virtual mot ouput port", VIRTUAL_PORT_INDEX);
+		}
+		println!();
+	}
+}
+
+/// Checks whether device output ports disappeared or came back, on the same
+/// cadence as the input ports, until the `MidiOut` is dropped.
+fn watch_outputs(outputs: Weak<Mutex<Vec<OutputPort>>>) {
+	let watcher = MidiOutput::new("midir reading output").unwrap();
+	loop {
+		thread::sleep(HOT_PLUG_POLL_INTERVAL);
+		let Some(outputs) = outputs.upgrade() else {
+			return;
+		};
+		let watched_names = output_port_names(&watcher);
+		for output in outputs.lock().unwrap().iter_mut() {
+			output.check(&watched_names);
+		}
+	}
+}
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Name of a MIDI note number with middle C (60) as C4, e.g. `C#4` for 61.
+pub fn note_name(note: u8) -> String {
+	format!("{}{}", NOTE_NAMES[(note % 12) as usize], (note / 12) as i32 - 1)
+}
+
+/// Parses a note number (`60`) or a note name with middle C as C4 (`C4`, `F#3`,
+/// `Bb-1`).
+pub fn parse_note(text: &str) -> Result<u8, String> {
+	if let Ok(note) = text.parse::<u8>() {
+		return if note < 128 { Ok(note) } else { Err(format!("Note {} out of range 0-127", note)) };
+	}
+	let error = || format!("Invalid note '{}', expects a number or a name like C4 or F#3", text);
+	let mut chars = text.chars();
+	let letter = chars.next().ok_or_else(error)?.to_ascii_uppercase();
+	let mut pitch_class = NOTE_NAMES.iter().position(|n| n.len() == 1 && n.starts_with(letter)).ok_or_else(error)? as i32;
+	let rest = chars.as_str();
+	let octave = if let Some(octave) = rest.strip_prefix('#') {
+		pitch_class += 1;
+		octave
+	} else if let Some(octave) = rest.strip_prefix('b') {
+		pitch_class -= 1;
+		octave
+	} else {
+		rest
+	};
+	let octave: i32 = octave.parse().map_err(|_| error())?;
+	let note = (octave + 1) * 12 + pitch_class;
+	u8::try_from(note).ok().filter(|n| *n < 128).ok_or(format!("Note {} out of range C-1 to G9", text))
+}
+
+fn parse_number(text: &str, what: &str, min: i32, max: i32) -> Result<i32, String> {
+	match text.parse::<i32>() {
+		Ok(value) if (min..=max).contains(&value) => Ok(value),
+		_ => Err(format!("Invalid {} '{}', expects {} to {}", what, text, min, max)),
+	}
+}
+
+fn parse_hex_byte(text: &str) -> Result<u8, String> {
+	let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+	if digits.len() > 2 {
+		return Err(format!("Invalid hex byte '{}'", text));
+	}
+	u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex byte '{}'", text))
+}
+
+/// Parses a textual MIDI message: either hex bytes (`90 3C 64`), which are sent
+/// as is, or a mnemonic with a one based channel (`noteon 1 C4 100`, `cc 1 7 127`,
+/// `pitchbend 1 -8192`, `sysex F0 7E 7F 06 01 F7`, `clock`). Mnemonics take
+/// precedence, so `cc` is never read as the byte 0xCC.
+pub fn parse_message_text(line: &str) -> Result<Vec<u8>, String> {
+	let tokens: Vec<&str> = line.split_whitespace().collect();
+	let (keyword, args) = tokens.split_first().ok_or("Empty message")?;
+	let expect = |count: std::ops::RangeInclusive<usize>, usage: &str| {
+		if count.contains(&args.len()) { Ok(()) } else { Err(format!("Usage: {}", usage)) }
+	};
+	let channel = |text: &str| parse_number(text, "channel", 1, 16).map(|c| c as u8 - 1);
+	let data = |text: &str, what: &str| parse_number(text, what, 0, 127).map(|v| v as u8);
+
+	let keyword = keyword.to_lowercase();
+	let message = match keyword.as_str() {
+		"noteon" | "on" => {
+			expect(3..=3, "noteon CHANNEL NOTE VELOCITY")?;
+			MidiMessage::NoteOn { channel: channel(args[0])?, note: parse_note(args[1])?, velocity: data(args[2], "velocity")? }
+		}
+		"noteoff" | "off" => {
+			expect(2..=3, "noteoff CHANNEL NOTE [VELOCITY]")?;
+			let velocity = args.get(2).map_or(Ok(0), |v| data(v, "velocity"))?;
+			MidiMessage::NoteOff { channel: channel(args[0])?, note: parse_note(args[1])?, velocity }
+		}
+		"polyaftertouch" | "polyat" => {
+			expect(3..=3, "polyaftertouch CHANNEL NOTE PRESSURE")?;
+			MidiMessage::PolyAftertouch { channel: channel(args[0])?, note: parse_note(args[1])?, pressure: data(args[2], "pressure")? }
+		}
+		"cc" | "controlchange" => {
+			expect(3..=3, "cc CHANNEL CONTROLLER VALUE")?;
+			MidiMessage::ControlChange { channel: channel(args[0])?, controller: data(args[1], "controller")?, value: data(args[2], "value")? }
+		}
+		"pc" | "program" | "programchange" => {
+			expect(2..=2, "program CHANNEL PROGRAM")?;
+			MidiMessage::ProgramChange { channel: channel(args[0])?, program: data(args[1], "program")? }
+		}
+		"aftertouch" | "at" => {
+			expect(2..=2, "aftertouch CHANNEL PRESSURE")?;
+			MidiMessage::ChannelAftertouch { channel: channel(args[0])?, pressure: data(args[1], "pressure")? }
+		}
+		"pitchbend" | "pb" => {
+			expect(2..=2, "pitchbend CHANNEL VALUE (-8192 to 8191)")?;
+			let value = parse_number(args[1], "pitch bend", -8192, 8191)?;
+			MidiMessage::PitchBend { channel: channel(args[0])?, value: (value + 8192) as u16 }
+		}
+		"sysex" => {
+			let mut bytes = args.iter().map(|b| parse_hex_byte(b)).collect::<Result<Vec<u8>, String>>()?;
+			if bytes.first() != Some(&0xF0) {
+				bytes.insert(0, 0xF0);
+			}
+			if bytes.len() < 2 || bytes.last() != Some(&0xF7) {
+				bytes.push(0xF7);
+			}
+			if bytes[1..bytes.len() - 1].iter().any(|b| *b >= 0x80) {
+				return Err(String::from("SysEx data bytes should be below 80"));
+			}
+			MidiMessage::SysEx(bytes)
+		}
+		"mtc" | "mtcquarterframe" => {
+			expect(2..=2, "mtc PIECE VALUE")?;
+			let piece = parse_number(args[0], "piece", 0, 7)? as u8;
+			MidiMessage::MtcQuarterFrame(piece << 4 | parse_number(args[1], "value", 0, 15)? as u8)
+		}
+		"songposition" | "spp" => {
+			expect(1..=1, "songposition BEATS")?;
+			MidiMessage::SongPosition(parse_number(args[0], "song position", 0, 0x3FFF)? as u16)
+		}
+		"songselect" => {
+			expect(1..=1, "songselect SONG")?;
+			MidiMessage::SongSelect(data(args[0], "song")?)
+		}
+		"tunerequest" | "clock" | "start" | "continue" | "stop" | "activesensing" | "reset" => {
+			expect(0..=0, &keyword)?;
+			match keyword.as_str() {
+				"tunerequest" => MidiMessage::TuneRequest,
+				"clock" => MidiMessage::TimingClock,
+				"start" => MidiMessage::Start,
+				"continue" => MidiMessage::Continue,
+				"stop" => MidiMessage::Stop,
+				"activesensing" => MidiMessage::ActiveSensing,
+				_ => MidiMessage::Reset,
+			}
+		}
+		_ => {
+			return tokens
+				.iter()
+				.map(|b| parse_hex_byte(b))
+				.collect::<Result<Vec<u8>, String>>()
+				.map_err(|e| format!("{}: not a message type or hex bytes", e));
+		}
+	};
+	Ok(message.encode())
+}
+
+/// A decoded MIDI message. Channels are zero based, as on the wire, but are
+/// printed one based.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiMessage {
+	NoteOff { channel: u8, note: u8, velocity: u8 },
+	NoteOn { channel: u8, note: u8, velocity: u8 },
+	PolyAftertouch { channel: u8, note: u8, pressure: u8 },
+	ControlChange { channel: u8, controller: u8, value: u8 },
+	ProgramChange { channel: u8, program: u8 },
+	ChannelAftertouch { channel: u8, pressure: u8 },
+	/// 14-bit value, 8192 is the center.
+	PitchBend { channel: u8, value: u16 },
+	/// Complete message including the leading 0xF0 and trailing 0xF7.
+	SysEx(Vec<u8>),
+	MtcQuarterFrame(u8),
+	SongPosition(u16),
+	SongSelect(u8),
+	TuneRequest,
+	TimingClock,
+	Start,
+	Continue,
+	Stop,
+	ActiveSensing,
+	Reset,
+}
+
+impl MidiMessage {
+	/// Decodes a single complete MIDI message.
+	pub fn parse(bytes: &[u8]) -> Result<MidiMessage, String> {
+		let Some(&status) = bytes.first() else {
+			return Err(String::from("Empty MIDI message"));
+		};
+		if status < 0x80 {
+			return Err(format!("Expected a status byte, got {:02X}", status));
+		}
+		let data_length = match status {
+			0x80..=0xBF | 0xE0..=0xEF | 0xF2 => 2,
+			0xC0..=0xDF | 0xF1 | 0xF3 => 1,
+			0xF0 => bytes.len() - 1,
+			0xF6 | 0xF8..=0xFF => 0,
+			_ => return Err(format!("Undefined status byte {:02X}", status)),
+		};
+		if bytes.len() != data_length + 1 {
+			return Err(format!("Expected {} data bytes after {:02X}, got {}", data_length, status, bytes.len() - 1));
+		}
+		if status != 0xF0 && bytes[1..].iter().any(|&b| b > 0x7F) {
+			return Err(format!("Data byte out of range in {:02X?}", bytes));
+		}
+
+		let channel = status & 0x0F;
+		let data = |i: usize| bytes[i];
+		let message = match status {
+			0x80..=0x8F => MidiMessage::NoteOff { channel, note: data(1), velocity: data(2) },
+			0x90..=0x9F => MidiMessage::NoteOn { channel, note: data(1), velocity: data(2) },
+			0xA0..=0xAF => MidiMessage::PolyAftertouch { channel, note: data(1), pressure: data(2) },
+			0xB0..=0xBF => MidiMessage::ControlChange { channel, controller: data(1), value: data(2) },
+			0xC0..=0xCF => MidiMessage::ProgramChange { channel, program: data(1) },
+			0xD0..=0xDF => MidiMessage::ChannelAftertouch { channel, pressure: data(1) },
+			0xE0..=0xEF => MidiMessage::PitchBend { channel, value: data(1) as u16 | (data(2) as u16) << 7 },
+			0xF0 => {
+				if bytes.last() != Some(&0xF7) {
+					return Err(String::from("SysEx message does not end with F7"));
+				}
+				MidiMessage::SysEx(bytes.to_vec())
+			}
+			0xF1 => MidiMessage::MtcQuarterFrame(data(1)),
+			0xF2 => MidiMessage::SongPosition(data(1) as u16 | (data(2) as u16) << 7),
+			0xF3 => MidiMessage::SongSelect(data(1)),
+			0xF6 => MidiMessage::TuneRequest,
+			0xF8 => MidiMessage::TimingClock,
+			0xFA => MidiMessage::Start,
+			0xFB => MidiMessage::Continue,
+			0xFC => MidiMessage::Stop,
+			0xFE => MidiMessage::ActiveSensing,
+			0xFF => MidiMessage::Reset,
+			_ => return Err(format!("Undefined status byte {:02X}", status)),
+		};
+		Ok(message)
+	}
+
+	/// Encodes the message into its MIDI bytes.
+	pub fn encode(&self) -> Vec<u8> {
+		match self {
+			MidiMessage::NoteOff { channel, note, velocity } => vec![0x80 | channel, *note, *velocity],
+			MidiMessage::NoteOn { channel, note, velocity } => vec![0x90 | channel, *note, *velocity],
+			MidiMessage::PolyAftertouch { channel, note, pressure } => vec![0xA0 | channel, *note, *pressure],
+			MidiMessage::ControlChange { channel, controller, value } => vec![0xB0 | channel, *controller, *value],
+			MidiMessage::ProgramChange { channel, program } => vec![0xC0 | channel, *program],
+			MidiMessage::ChannelAftertouch { channel, pressure } => vec![0xD0 | channel, *pressure],
+			MidiMessage::PitchBend { channel, value } => vec![0xE0 | channel, (value & 0x7F) as u8, (value >> 7) as u8],
+			MidiMessage::SysEx(bytes) => bytes.clone(),
+			MidiMessage::MtcQuarterFrame(value) => vec![0xF1, *value],
+			MidiMessage::SongPosition(position) => vec![0xF2, (position & 0x7F) as u8, (position >> 7) as u8],
+			MidiMessage::SongSelect(song) => vec![0xF3, *song],
+			MidiMessage::TuneRequest => vec![0xF6],
+			MidiMessage::TimingClock => vec![0xF8],
+			MidiMessage::Start => vec![0xFA],
+			MidiMessage::Continue => vec![0xFB],
+			MidiMessage::Stop => vec![0xFC],
+			MidiMessage::ActiveSensing => vec![0xFE],
+			MidiMessage::Reset => vec![0xFF],
+		}
+	}
+}
+
+impl MidiMessage {
+	/// Short name of the message type, e.g. `NoteOn` or `CC`.
+	pub fn name(&self) -> &'static str {
+		match self {
+			MidiMessage::NoteOff { .. } => "NoteOff",
+			MidiMessage::NoteOn { .. } => "NoteOn",
+			MidiMessage::PolyAftertouch { .. } => "PolyAftertouch",
+			MidiMessage::ControlChange { .. } => "CC",
+			MidiMessage::ProgramChange { .. } => "ProgramChange",
+			MidiMessage::ChannelAftertouch { .. } => "Aftertouch",
+			MidiMessage::PitchBend { .. } => "PitchBend",
+			MidiMessage::SysEx(_) => "SysEx",
+			MidiMessage::MtcQuarterFrame(_) => "MTCQuarterFrame",
+			MidiMessage::SongPosition(_) => "SongPosition",
+			MidiMessage::SongSelect(_) => "SongSelect",
+			MidiMessage::TuneRequest => "TuneRequest",
+			MidiMessage::TimingClock => "Clock",
+			MidiMessage::Start => "Start",
+			MidiMessage::Continue => "Continue",
+			MidiMessage::Stop => "Stop",
+			MidiMessage::ActiveSensing => "ActiveSensing",
+			MidiMessage::Reset => "Reset",
+		}
+	}
+
+	/// The zero based channel of a channel message.
+	pub fn channel(&self) -> Option<u8> {
+		match self {
+			MidiMessage::NoteOff { channel, .. }
+			| MidiMessage::NoteOn { channel, .. }
+			| MidiMessage::PolyAftertouch { channel, .. }
+			| MidiMessage::ControlChange { channel, .. }
+			| MidiMessage::ProgramChange { channel, .. }
+			| MidiMessage::ChannelAftertouch { channel, .. }
+			| MidiMessage::PitchBend { channel, .. } => Some(*channel),
+			_ => None,
+		}
+	}
+
+	/// The decoded data fields of the message by name. The pitch bend value is
+	/// relative to the center.
+	pub fn fields(&self) -> Vec<(&'static str, i32)> {
+		match self {
+			MidiMessage::NoteOff { note, velocity, .. } | MidiMessage::NoteOn { note, velocity, .. } =>
+				vec![("note", *note as i32), ("velocity", *velocity as i32)],
+			MidiMessage::PolyAftertouch { note, pressure, .. } =>
+				vec![("note", *note as i32), ("pressure", *pressure as i32)],
+			MidiMessage::ControlChange { controller, value, .. } =>
+				vec![("controller", *controller as i32), ("value", *value as i32)],
+			MidiMessage::ProgramChange { program, .. } => vec![("program", *program as i32)],
+			MidiMessage::ChannelAftertouch { pressure, .. } => vec![("pressure", *pressure as i32)],
+			MidiMessage::PitchBend { value, .. } => vec![("value", *value as i32 - 8192)],
+			MidiMessage::MtcQuarterFrame(value) =>
+				vec![("piece", (value >> 4) as i32), ("value", (value & 0x0F) as i32)],
+			MidiMessage::SongPosition(position) => vec![("position", *position as i32)],
+			MidiMessage::SongSelect(song) => vec![("song", *song as i32)],
+			_ => Vec::new(),
+		}
+	}
+}
+
+impl fmt::Display for MidiMessage {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if let Some(channel) = self.channel() {
+			write!(f, "ch{} ", channel + 1)?;
+		}
+		write!(f, "{}", self.name())?;
+		match self {
+			MidiMessage::NoteOff { note, velocity, .. } | MidiMessage::NoteOn { note, velocity, .. } =>
+				write!(f, " {} vel={}", note_name(*note), velocity),
+			MidiMessage::PolyAftertouch { note, pressure, .. } =>
+				write!(f, " {} pressure={}", note_name(*note), pressure),
+			MidiMessage::ControlChange { controller, value, .. } => write!(f, " {} val={}", controller, value),
+			MidiMessage::ProgramChange { program, .. } => write!(f, " {}", program),
+			MidiMessage::ChannelAftertouch { pressure, .. } => write!(f, " pressure={}", pressure),
+			MidiMessage::PitchBend { value, .. } => write!(f, " {}", *value as i32 - 8192),
+			MidiMessage::SysEx(bytes) => {
+				for byte in bytes {
+					write!(f, " {:02X}", byte)?;
+				}
+				Ok(())
+			}
+			MidiMessage::MtcQuarterFrame(value) => write!(f, " type={} val={}", value >> 4, value & 0x0F),
+			MidiMessage::SongPosition(position) => write!(f, " {}", position),
+			MidiMessage::SongSelect(song) => write!(f, " {}", song),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn names() -> Vec<String> {
+		vec![
+			String::from("Midi Through Port-0"),
+			String::from("Teensy MIDI Port 1"),
+			String::from("Teensy MIDI Port 2"),
+			String::from("nanoKONTROL2"),
+		]
+	}
+
+	#[test]
+	fn test_find_port_after_replug() {
+		let names = vec![
+			String::from("Midi Through:Midi Through Port-0 14:0"),
+			String::from("Teensy MIDI:Teensy MIDI MIDI 1 28:0"),
+		];
+		assert_eq!(find_port(&names, "Teensy MIDI:Teensy MIDI MIDI 1 28:0"), Some(1));
+		assert_eq!(find_port(&names, "Teensy MIDI:Teensy MIDI MIDI 1 24:0"), Some(1));
+		assert_eq!(find_port(&names, "Teensy MIDI:Teensy MIDI MIDI 2 24:0"), None);
+		assert_eq!(port_identity("IAC Driver Bus 1"), "IAC Driver Bus 1");
+	}
+
+	#[test]
+	fn test_parse_midi_message() {
+		assert_eq!(MidiMessage::parse(&[0x92, 60, 100]), Ok(MidiMessage::NoteOn { channel: 2, note: 60, velocity: 100 }));
+		assert_eq!(MidiMessage::parse(&[0xE0, 0x00, 0x40]), Ok(MidiMessage::PitchBend { channel: 0, value: 8192 }));
+		assert_eq!(MidiMessage::parse(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]), Ok(MidiMessage::SysEx(vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7])));
+		assert_eq!(MidiMessage::parse(&[0xF8]), Ok(MidiMessage::TimingClock));
+		assert!(MidiMessage::parse(&[0x90, 60]).is_err());
+		assert!(MidiMessage::parse(&[0x90, 60, 128]).is_err());
+		assert!(MidiMessage::parse(&[0x3C]).is_err());
+		assert!(MidiMessage::parse(&[0xF0, 0x01]).is_err());
+	}
+
+	#[test]
+	fn test_encode_midi_message() {
+		let messages: [&[u8]; 7] = [
+			&[0x8F, 0, 0], &[0xB3, 7, 127], &[0xC1, 5], &[0xD4, 30], &[0xE2, 0x7F, 0x7F], &[0xF2, 0x10, 0x01], &[0xF1, 0x35],
+		];
+		for bytes in messages {
+			assert_eq!(MidiMessage::parse(bytes).unwrap().encode(), bytes.to_vec());
+		}
+	}
+
+	#[test]
+	fn test_display_midi_message() {
+		assert_eq!(MidiMessage::NoteOn { channel: 2, note: 60, velocity: 100 }.to_string(), "ch3 NoteOn C4 vel=100");
+		assert_eq!(MidiMessage::ControlChange { channel: 0, controller: 7, value: 127 }.to_string(), "ch1 CC 7 val=127");
+		assert_eq!(MidiMessage::PitchBend { channel: 0, value: 0 }.to_string(), "ch1 PitchBend -8192");
+		assert_eq!(note_name(0), "C-1");
+		assert_eq!(note_name(61), "C#4");
+		assert_eq!(note_name(127), "G9");
+	}
+
+	#[test]
+	fn test_parse_port_spec() {
+		assert_eq!(PortSpec::from_str("3").unwrap(), PortSpec::Index(3));
+		assert_eq!(PortSpec::from_str("teensy").unwrap(), PortSpec::Name(String::from("teensy")));
+		assert_eq!(PortSpec::from_str("/Port [12]$/").unwrap(), PortSpec::Regex(String::from("Port [12]$")));
+		assert!(PortSpec::from_str("/(/").is_err());
+		assert!(PortSpec::from_str("").is_err());
+	}
+
+	#[test]
+	fn test_resolve_port_spec() {
+		let names = names();
+		assert_eq!(PortSpec::Index(1).resolve(&names), Ok(1));
+		assert!(PortSpec::Index(4).resolve(&names).is_err());
+		assert_eq!(PortSpec::Name(String::from("Teensy MIDI Port 2")).resolve(&names), Ok(2));
+		assert_eq!(PortSpec::Name(String::from("nanokontrol")).resolve(&names), Ok(3));
+		assert_eq!(PortSpec::Regex(String::from("Port 1$")).resolve(&names), Ok(1));
+	}
+
+	#[test]
+	fn test_resolve_port_spec_errors_list_candidates() {
+		let names = names();
+		let ambiguous = PortSpec::Name(String::from("teensy")).resolve(&names).unwrap_err();
+		assert!(ambiguous.contains("1: Teensy MIDI Port 1"));
+		assert!(ambiguous.contains("2: Teensy MIDI Port 2"));
+		assert!(!ambiguous.contains("nanoKONTROL2"));
+
+		let missing = PortSpec::Name(String::from("launchpad")).resolve(&names).unwrap_err();
+		assert!(missing.contains("0: Midi Through Port-0"));
+		assert!(missing.contains("3: nanoKONTROL2"));
+	}
+
+	#[test]
+	fn test_parse_note() {
+		assert_eq!(parse_note("60"), Ok(60));
+		assert_eq!(parse_note("C4"), Ok(60));
+		assert_eq!(parse_note("c#4"), Ok(61));
+		assert_eq!(parse_note("Db4"), Ok(61));
+		assert_eq!(parse_note("C-1"), Ok(0));
+		assert_eq!(parse_note("G9"), Ok(127));
+		assert!(parse_note("G#9").is_err());
+		assert!(parse_note("H4").is_err());
+		assert!(parse_note("128").is_err());
+	}
+
+	#[test]
+	fn test_parse_message_text() {
+		assert_eq!(parse_message_text("90 3C 64"), Ok(vec![0x90, 0x3C, 0x64]));
+		assert_eq!(parse_message_text("0xF8"), Ok(vec![0xF8]));
+		assert_eq!(parse_message_text("noteon 1 C4 100"), Ok(vec![0x90, 60, 100]));
+		assert_eq!(parse_message_text("NoteOff 16 61"), Ok(vec![0x8F, 61, 0]));
+		assert_eq!(parse_message_text("cc 1 7 127"), Ok(vec![0xB0, 7, 127]));
+		assert_eq!(parse_message_text("program 2 5"), Ok(vec![0xC1, 5]));
+		assert_eq!(parse_message_text("pitchbend 1 -8192"), Ok(vec![0xE0, 0, 0]));
+		assert_eq!(parse_message_text("sysex F0 7E 7F 06 01 F7"), Ok(vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]));
+		assert_eq!(parse_message_text("sysex 7E 7F"), Ok(vec![0xF0, 0x7E, 0x7F, 0xF7]));
+		assert_eq!(parse_message_text("clock"), Ok(vec![0xF8]));
+		assert_eq!(parse_message_text("spp 16"), Ok(vec![0xF2, 16, 0]));
+
+		assert!(parse_message_text("cc 0 7 127").unwrap_err().contains("channel"));
+		assert!(parse_message_text("noteon 1 C4").unwrap_err().starts_with("Usage"));
+		assert!(parse_message_text("sysex F0 80 F7").is_err());
+		assert!(parse_message_text("clock 1").is_err());
+		assert!(parse_message_text("hello").is_err());
+	}
+}
@@ -0,0 +1,283 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The four MTC frame rates, in the order of their rate code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameRate {
+    Fps24,
+    Fps25,
+    /// 29.97 fps drop frame: frames 0 and 1 are skipped at the start of each minute,
+    /// except every tenth minute.
+    Fps2997Df,
+    Fps30,
+}
+
+impl FrameRate {
+    fn from_code(code: u8) -> FrameRate {
+        match code & 0x03 {
+            0 => FrameRate::Fps24,
+            1 => FrameRate::Fps25,
+            2 => FrameRate::Fps2997Df,
+            _ => FrameRate::Fps30,
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            FrameRate::Fps24 => 0,
+            FrameRate::Fps25 => 1,
+            FrameRate::Fps2997Df => 2,
+            FrameRate::Fps30 => 3,
+        }
+    }
+
+    /// Frames per timecode second.
+    pub fn frames_per_second(&self) -> u64 {
+        match self {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps2997Df | FrameRate::Fps30 => 30,
+        }
+    }
+
+    /// The real duration of a frame.
+    pub fn frame_duration(&self) -> Duration {
+        match self {
+            FrameRate::Fps2997Df => Duration::from_secs_f64(1001.0 / 30000.0),
+            other => Duration::from_secs_f64(1.0 / other.frames_per_second() as f64),
+        }
+    }
+}
+
+impl FromStr for FrameRate {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<FrameRate, String> {
+        match text.to_lowercase().as_str() {
+            "24" => Ok(FrameRate::Fps24),
+            "25" => Ok(FrameRate::Fps25),
+            "29.97" | "29.97df" | "2997" | "df" => Ok(FrameRate::Fps2997Df),
+            "30" => Ok(FrameRate::Fps30),
+            _ => Err(String::from("Expects one of: 24, 25, 29.97df, 30")),
+        }
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameRate::Fps2997Df => write!(f, "29.97df"),
+            other => write!(f, "{}", other.frames_per_second()),
+        }
+    }
+}
+
+/// A SMPTE timecode as carried by MIDI Time Code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: FrameRate,
+}
+
+const DROP_FRAMES_PER_MINUTE: u64 = 30 * 60 - 2;
+const DROP_FRAMES_PER_TEN_MINUTES: u64 = DROP_FRAMES_PER_MINUTE * 10 + 2;
+
+impl Timecode {
+    /// Parses `hh:mm:ss:ff`; `;` is accepted as separator as well.
+    pub fn parse(text: &str, rate: FrameRate) -> Result<Timecode, String> {
+        let parts: Vec<&str> = text.split([':', ';']).collect();
+        let numbers: Vec<u8> = parts.iter().filter_map(|p| p.parse().ok()).collect();
+        if parts.len() != 4 || numbers.len() != 4 {
+            return Err(format!("Invalid timecode '{}', expects hh:mm:ss:ff", text));
+        }
+        let timecode = Timecode { hours: numbers[0], minutes: numbers[1], seconds: numbers[2], frames: numbers[3], rate };
+        if timecode.hours > 23 || timecode.minutes > 59 || timecode.seconds > 59 || timecode.frames as u64 >= rate.frames_per_second() {
+            return Err(format!("Timecode '{}' out of range for {} fps", text, rate));
+        }
+        if rate == FrameRate::Fps2997Df && timecode.seconds == 0 && timecode.frames < 2 && !timecode.minutes.is_multiple_of(10) {
+            return Err(format!("Timecode '{}' does not exist in drop frame, frames 0 and 1 are dropped", text));
+        }
+        Ok(timecode)
+    }
+
+    /// The number of frames since 00:00:00:00.
+    pub fn frame_number(&self) -> u64 {
+        let fps = self.rate.frames_per_second();
+        let total_minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let frames = (total_minutes * 60 + self.seconds as u64) * fps + self.frames as u64;
+        if self.rate == FrameRate::Fps2997Df {
+            frames - 2 * (total_minutes - total_minutes / 10)
+        } else {
+            frames
+        }
+    }
+
+    /// The timecode of a frame number, wrapping around after 24 hours.
+    pub fn from_frame_number(frame_number: u64, rate: FrameRate) -> Timecode {
+        let fps = rate.frames_per_second();
+        let mut frames = frame_number;
+        if rate == FrameRate::Fps2997Df {
+            frames %= DROP_FRAMES_PER_TEN_MINUTES * 6 * 24;
+            let tens = frames / DROP_FRAMES_PER_TEN_MINUTES;
+            let rest = frames % DROP_FRAMES_PER_TEN_MINUTES;
+            let dropped = if rest < 2 { 0 } else { 2 * ((rest - 2) / DROP_FRAMES_PER_MINUTE) };
+            frames += 18 * tens + dropped;
+        }
+        Timecode {
+            hours: ((frames / (fps * 3600)) % 24) as u8,
+            minutes: ((frames / (fps * 60)) % 60) as u8,
+            seconds: ((frames / fps) % 60) as u8,
+            frames: (frames % fps) as u8,
+            rate,
+        }
+    }
+
+    pub fn add_frames(&self, frames: u64) -> Timecode {
+        Timecode::from_frame_number(self.frame_number() + frames, self.rate)
+    }
+
+    /// The data byte of quarter frame `piece` (0-7).
+    pub fn quarter_frame(&self, piece: u8) -> u8 {
+        let nibble = match piece {
+            0 => self.frames & 0x0F,
+            1 => self.frames >> 4,
+            2 => self.seconds & 0x0F,
+            3 => self.seconds >> 4,
+            4 => self.minutes & 0x0F,
+            5 => self.minutes >> 4,
+            6 => self.hours & 0x0F,
+            _ => (self.hours >> 4) | self.rate.code() << 1,
+        };
+        (piece & 0x07) << 4 | nibble
+    }
+
+    /// The full frame SysEx message, sent when jumping to a position.
+    pub fn full_frame(&self) -> Vec<u8> {
+        vec![0xF0, 0x7F, 0x7F, 0x01, 0x01, self.rate.code() << 5 | self.hours, self.minutes, self.seconds, self.frames, 0xF7]
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}:{:02}", self.hours, self.minutes, self.seconds, self.frames)
+    }
+}
+
+/// Decodes timecode from incoming quarter frame and full frame messages.
+#[derive(Default)]
+pub struct MtcReader {
+    nibbles: [u8; 8],
+    /// Quarter frames received in order since piece 0.
+    received: usize,
+}
+
+impl MtcReader {
+    pub fn new() -> MtcReader {
+        MtcReader::default()
+    }
+
+    /// Returns the timecode when a message completes one: a full frame message, or
+    /// the last of eight quarter frames.
+    pub fn handle(&mut self, message: &[u8]) -> Option<Timecode> {
+        match message {
+            [0xF1, data] => self.quarter_frame(*data),
+            [0xF0, 0x7F, _, 0x01, 0x01, hours, minutes, seconds, frames, 0xF7] => {
+                self.received = 0;
+                Some(Timecode {
+                    hours: hours & 0x1F,
+                    minutes: *minutes,
+                    seconds: *seconds,
+                    frames: *frames,
+                    rate: FrameRate::from_code(hours >> 5),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn quarter_frame(&mut self, data: u8) -> Option<Timecode> {
+        let piece = (data >> 4) as usize;
+        if piece == 0 {
+            self.received = 0;
+        }
+        if piece != self.received {
+            // Out of order, e.g. when the timecode runs backwards
+            self.received = 0;
+            return None;
+        }
+        self.nibbles[piece] = data & 0x0F;
+        self.received += 1;
+        if self.received < 8 {
+            return None;
+        }
+
+        self.received = 0;
+        let n = &self.nibbles;
+        let timecode = Timecode {
+            hours: n[6] | (n[7] & 0x01) << 4,
+            minutes: n[4] | (n[5] & 0x03) << 4,
+            seconds: n[2] | (n[3] & 0x03) << 4,
+            frames: n[0] | (n[1] & 0x01) << 4,
+            rate: FrameRate::from_code(n[7] >> 1),
+        };
+        // The eight quarter frames take two frames to arrive and carry the time of
+        // the first one
+        Some(timecode.add_frames(2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timecode() {
+        let timecode = Timecode::parse("01:02:03:04", FrameRate::Fps25).unwrap();
+        assert_eq!(timecode.to_string(), "01:02:03:04");
+        assert_eq!(timecode.frame_number(), ((3600 + 2 * 60 + 3) * 25 + 4) as u64);
+        assert!(Timecode::parse("01:02:03:25", FrameRate::Fps25).is_err());
+        assert!(Timecode::parse("00:01:00:00", FrameRate::Fps2997Df).is_err());
+        assert!(Timecode::parse("00:10:00:00", FrameRate::Fps2997Df).is_ok());
+        assert!(Timecode::parse("1:2:3", FrameRate::Fps25).is_err());
+    }
+
+    #[test]
+    fn test_drop_frame_numbers() {
+        let rate = FrameRate::Fps2997Df;
+        let before = Timecode::parse("00:00:59:29", rate).unwrap();
+        assert_eq!(before.add_frames(1).to_string(), "00:01:00:02");
+        let tenth = Timecode::parse("00:09:59:29", rate).unwrap();
+        assert_eq!(tenth.add_frames(1).to_string(), "00:10:00:00");
+        // An hour of 29.97 drop frame timecode holds 107892 frames
+        assert_eq!(Timecode::parse("01:00:00:00", rate).unwrap().frame_number(), 107_892);
+        for n in [0, 1799, 1800, 17_981, 17_982, 107_891, 1_000_000] {
+            assert_eq!(Timecode::from_frame_number(n, rate).frame_number(), n);
+        }
+    }
+
+    #[test]
+    fn test_quarter_frames_round_trip() {
+        let timecode = Timecode::parse("23:59:58:28", FrameRate::Fps2997Df).unwrap();
+        let mut reader = MtcReader::new();
+        for piece in 0..7 {
+            assert_eq!(reader.handle(&[0xF1, timecode.quarter_frame(piece)]), None);
+        }
+        assert_eq!(reader.handle(&[0xF1, timecode.quarter_frame(7)]), Some(timecode.add_frames(2)));
+        assert_eq!(timecode.quarter_frame(7), 0x75);
+
+        // Pieces out of order do not produce a timecode
+        assert_eq!(reader.handle(&[0xF1, timecode.quarter_frame(7)]), None);
+    }
+
+    #[test]
+    fn test_full_frame() {
+        let timecode = Timecode::parse("10:20:30:12", FrameRate::Fps24).unwrap();
+        let message = timecode.full_frame();
+        assert_eq!(message, vec![0xF0, 0x7F, 0x7F, 0x01, 0x01, 10, 20, 30, 12, 0xF7]);
+        assert_eq!(MtcReader::new().handle(&message), Some(timecode));
+    }
+}
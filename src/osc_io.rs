@@ -1,11 +1,15 @@
-use std::sync::mpsc::Sender;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use std::net::UdpSocket;
-use std::net::SocketAddrV4;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::thread;
 
 use rand::Rng; // 0.8.0
 
@@ -14,48 +18,623 @@ use rosc::OscMessage;
 use rosc::encoder;
 use rosc::OscType;
 
+use crate::resolver::{HostPort, ResolvedAddress};
+
+use crate::output_format::{self, OutputFormat};
+
+/// How OSC packets are delimited on a TCP stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    /// OSC 1.0: each packet is preceded by its size as a big-endian int32.
+    LengthPrefix,
+    /// OSC 1.1: packets are SLIP encoded (RFC 1055), with an END byte on both sides.
+    Slip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OscTransport {
+    Udp,
+    Tcp(Framing),
+}
+
+/// An OSC endpoint such as `udp://127.0.0.1:5566`, `tcp://[::1]:5566` for
+/// length-prefixed TCP or `tcp+slip://mixer.local:5566`. Without scheme UDP is used.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscUrl {
+    pub transport: OscTransport,
+    pub address: HostPort,
+}
+
+impl FromStr for OscUrl {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<OscUrl, String> {
+        let (transport, address) = match url.split_once("://") {
+            None => (OscTransport::Udp, url),
+            Some((scheme, address)) => match scheme.to_lowercase().as_str() {
+                "udp" => (OscTransport::Udp, address),
+                "tcp" => (OscTransport::Tcp(Framing::LengthPrefix), address),
+                "tcp+slip" => (OscTransport::Tcp(Framing::Slip), address),
+                _ => return Err(format!("Unknown OSC transport '{}', expects udp, tcp or tcp+slip", scheme)),
+            },
+        };
+        let address = HostPort::from_str(address)?;
+        Ok(OscUrl { transport, address })
+    }
+}
+
+impl OscUrl {
+    pub fn scheme(&self) -> &'static str {
+        match self.transport {
+            OscTransport::Udp => "udp",
+            OscTransport::Tcp(Framing::LengthPrefix) => "tcp",
+            OscTransport::Tcp(Framing::Slip) => "tcp+slip",
+        }
+    }
+
+    /// The DNS-SD service type receivers are announced as.
+    pub fn mdns_service_type(&self) -> &'static str {
+        match self.transport {
+            OscTransport::Udp => "_osc._udp",
+            OscTransport::Tcp(_) => "_osc._tcp",
+        }
+    }
+}
+
+impl fmt::Display for OscUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}", self.scheme(), self.address)
+    }
+}
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// Largest packet accepted from a stream, well above any SysEx dump.
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+impl Framing {
+    pub fn encode(&self, packet: &[u8]) -> Vec<u8> {
+        match self {
+            Framing::LengthPrefix => {
+                let mut frame = (packet.len() as u32).to_be_bytes().to_vec();
+                frame.extend(packet);
+                frame
+            }
+            Framing::Slip => {
+                let mut frame = vec![SLIP_END];
+                for &byte in packet {
+                    match byte {
+                        SLIP_END => frame.extend([SLIP_ESC, SLIP_ESC_END]),
+                        SLIP_ESC => frame.extend([SLIP_ESC, SLIP_ESC_ESC]),
+                        byte => frame.push(byte),
+                    }
+                }
+                frame.push(SLIP_END);
+                frame
+            }
+        }
+    }
+}
+
+/// Collects bytes read from a stream and splits them into packets.
+pub struct FrameDecoder {
+    framing: Framing,
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(framing: Framing) -> FrameDecoder {
+        FrameDecoder { framing, buffer: Vec::new() }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend(bytes);
+    }
+
+    /// The next complete packet, if all of its bytes arrived. Fails when a packet
+    /// is larger than `MAX_FRAME_SIZE`; the stream is then out of sync.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, String> {
+        match self.framing {
+            Framing::LengthPrefix => {
+                if self.buffer.len() < 4 {
+                    return Ok(None);
+                }
+                let size = u32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]) as usize;
+                if size > MAX_FRAME_SIZE {
+                    return Err(format!("Packet size {} too large", size));
+                }
+                if self.buffer.len() < 4 + size {
+                    return Ok(None);
+                }
+                let frame = self.buffer[4..4 + size].to_vec();
+                self.buffer.drain(..4 + size);
+                Ok(Some(frame))
+            }
+            Framing::Slip => loop {
+                let end = match self.buffer.iter().position(|b| *b == SLIP_END) {
+                    Some(end) => end,
+                    None if self.buffer.len() > MAX_FRAME_SIZE => return Err(String::from("SLIP packet too large")),
+                    None => return Ok(None),
+                };
+                let escaped: Vec<u8> = self.buffer.drain(..=end).take(end).collect();
+                // Empty frames come from the END byte which starts each packet
+                if escaped.is_empty() {
+                    continue;
+                }
+                let mut frame = Vec::with_capacity(escaped.len());
+                let mut bytes = escaped.into_iter();
+                while let Some(byte) = bytes.next() {
+                    frame.push(match byte {
+                        SLIP_ESC => match bytes.next() {
+                            Some(SLIP_ESC_END) => SLIP_END,
+                            Some(SLIP_ESC_ESC) => SLIP_ESC,
+                            Some(other) => other,
+                            None => break,
+                        },
+                        byte => byte,
+                    });
+                }
+                return Ok(Some(frame));
+            },
+        }
+    }
+}
+
+/// The TCP connection of an `OscSender`, made when the first packet is sent and
+/// made again when the connection breaks.
+struct TcpConnection {
+    stream: Option<TcpStream>,
+    decoder: FrameDecoder,
+}
+
+enum SenderSocket {
+    /// Bound on the first send, for the address family of the target.
+    Udp(Mutex<Option<UdpSocket>>),
+    Tcp(Mutex<TcpConnection>),
+}
+
 pub struct OscSender {
-    sock: UdpSocket,
-    to_addr: SocketAddrV4
+    sock: SenderSocket,
+    to_addr: ResolvedAddress,
+    transport: OscTransport,
 }
 
 impl OscSender {
+    /// Sends to an `OscUrl`; a plain host:port is sent to over UDP. A host name is
+    /// looked up again regularly, packets sent before it resolves are dropped.
     pub fn new(osc_target_address: String) -> OscSender {
-        let num = rand::thread_rng().gen_range(12000..13000);
-        let sock = UdpSocket::bind("0.0.0.0:".to_owned() + &num.to_string()).unwrap();
-        let to_addr = SocketAddrV4::from_str(&osc_target_address).unwrap();
+        let url = OscUrl::from_str(&osc_target_address).unwrap();
+        let sock = match url.transport {
+            OscTransport::Udp => SenderSocket::Udp(Mutex::new(None)),
+            OscTransport::Tcp(framing) => SenderSocket::Tcp(Mutex::new(TcpConnection {
+                stream: None,
+                decoder: FrameDecoder::new(framing),
+            })),
+        };
         OscSender {
-            sock: sock,
-            to_addr: to_addr
+            sock,
+            to_addr: ResolvedAddress::new(url.address),
+            transport: url.transport,
         }
     }
 
     pub fn send(&self, addr: String, osc_args: Vec<OscType>) {
-        let msg_buf = encoder::encode(&OscPacket::Message(OscMessage {
-            addr: addr,
+        self.send_packet(&OscPacket::Message(OscMessage {
+            addr,
             args: osc_args,
-        })).unwrap();
-        self.sock.send_to(&msg_buf, self.to_addr).unwrap();
+        }));
+    }
+
+    /// Sends a message or a bundle.
+    pub fn send_packet(&self, packet: &OscPacket) {
+        let msg_buf = match encoder::encode(packet) {
+            Ok(msg_buf) => msg_buf,
+            Err(e) => {
+                eprintln!("Could not encode OSC packet: {}", e);
+                return;
+            }
+        };
+        let result = match (&self.sock, self.transport) {
+            (SenderSocket::Udp(sock), _) => self.send_udp(&mut sock.lock().unwrap(), &msg_buf),
+            (SenderSocket::Tcp(connection), OscTransport::Tcp(framing)) => {
+                self.send_tcp(&mut connection.lock().unwrap(), &framing.encode(&msg_buf))
+            }
+            _ => unreachable!("TCP socket without TCP transport"),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not send OSC to {}: {}", self.to_addr.host_port(), e);
+        }
+    }
+
+    fn target(&self) -> io::Result<SocketAddr> {
+        self.to_addr
+            .get()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address not resolved yet"))
+    }
+
+    /// Sends a packet, binding a socket of the right address family first. The
+    /// family can change when the target name is resolved again.
+    fn send_udp(&self, sock: &mut Option<UdpSocket>, packet: &[u8]) -> io::Result<()> {
+        let target = self.target()?;
+        let family_changed = sock
+            .as_ref()
+            .and_then(|s| s.local_addr().ok())
+            .is_some_and(|local| local.is_ipv4() != target.is_ipv4());
+        if sock.is_none() || family_changed {
+            let num = rand::thread_rng().gen_range(12000..13000);
+            let any = if target.is_ipv4() { "0.0.0.0" } else { "[::]" };
+            *sock = Some(UdpSocket::bind(format!("{}:{}", any, num))?);
+        }
+        sock.as_ref().unwrap().send_to(packet, target)?;
+        Ok(())
+    }
+
+    /// Writes a frame, connecting first if needed. A broken connection is made again
+    /// once, e.g. when the receiver restarted.
+    fn send_tcp(&self, connection: &mut TcpConnection, frame: &[u8]) -> io::Result<()> {
+        for attempt in 0..2 {
+            if connection.stream.is_none() {
+                let stream = match TcpStream::connect(self.target()?) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        // The receiver may have moved to another address
+                        if self.to_addr.host_port().needs_lookup() {
+                            let _ = self.to_addr.refresh();
+                        }
+                        return Err(e);
+                    }
+                };
+                stream.set_nodelay(true)?;
+                connection.stream = Some(stream);
+            }
+            match connection.stream.as_mut().unwrap().write_all(frame) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    connection.stream = None;
+                    if attempt == 1 {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for a packet sent back to this sender, e.g. by a
+    /// reflector. Packets which do not decode are skipped.
+    pub fn receive(&self, timeout: Duration) -> Option<(OscPacket, SocketAddr)> {
+        // A zero timeout is not allowed and would block forever
+        let timeout = timeout.max(Duration::from_millis(1));
+        match &self.sock {
+            SenderSocket::Udp(sock) => {
+                let sock = sock.lock().unwrap();
+                let sock = match sock.as_ref() {
+                    Some(sock) => sock,
+                    None => {
+                        // Nothing was sent, so nothing can come back
+                        thread::sleep(timeout);
+                        return None;
+                    }
+                };
+                sock.set_read_timeout(Some(timeout)).ok()?;
+                let mut buf = [0u8; rosc::decoder::MTU];
+                let (size, from_addr) = sock.recv_from(&mut buf).ok()?;
+                let (_, packet) = rosc::decoder::decode_udp(&buf[..size]).ok()?;
+                Some((packet, from_addr))
+            }
+            SenderSocket::Tcp(connection) => {
+                let mut connection = connection.lock().unwrap();
+                let TcpConnection { stream, decoder } = &mut *connection;
+                let stream = stream.as_mut()?;
+                let from_addr = stream.peer_addr().ok()?;
+                let frame = match decoder.next_frame().ok()? {
+                    Some(frame) => frame,
+                    None => {
+                        stream.set_read_timeout(Some(timeout)).ok()?;
+                        let mut buf = [0u8; 4096];
+                        let size = stream.read(&mut buf).ok()?;
+                        decoder.push(&buf[..size]);
+                        decoder.next_frame().ok()??
+                    }
+                };
+                let (_, packet) = rosc::decoder::decode_udp(&frame).ok()?;
+                Some((packet, from_addr))
+            }
+        }
+    }
+}
+
+/// An OSC 1.0 address pattern: `?` matches any character, `*` any sequence of
+/// characters, `[a-z]` or `[!a-z]` a character in or not in a set and `{foo,bar}`
+/// one of several strings. Wildcards never match the `/` between address parts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddressPattern {
+    pattern: Vec<char>,
+}
+
+impl FromStr for AddressPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<AddressPattern, String> {
+        if !pattern.starts_with('/') {
+            return Err(format!("Invalid OSC address pattern '{}', it starts with /", pattern));
+        }
+        let mut open: Option<char> = None;
+        for c in pattern.chars() {
+            open = match (open, c) {
+                (None, '[') => Some(']'),
+                (None, '{') => Some('}'),
+                (Some(close), c) if c == close => None,
+                (Some(_), '[' | '{' | '/') | (None, ']' | '}') => {
+                    return Err(format!("Invalid OSC address pattern '{}', unbalanced {}", pattern, c));
+                }
+                (open, _) => open,
+            };
+        }
+        if let Some(close) = open {
+            return Err(format!("Invalid OSC address pattern '{}', missing {}", pattern, close));
+        }
+        Ok(AddressPattern { pattern: pattern.chars().collect() })
+    }
+}
+
+impl fmt::Display for AddressPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern.iter().collect::<String>())
+    }
+}
+
+impl AddressPattern {
+    pub fn matches(&self, address: &str) -> bool {
+        let address: Vec<char> = address.chars().collect();
+        match_pattern(&self.pattern, &address)
+    }
+
+    /// Whether the pattern has no wildcards and only matches itself.
+    pub fn is_literal(&self) -> bool {
+        !self.pattern.iter().any(|c| "?*[]{}".contains(*c))
+    }
+
+    /// Whether a message sent to `address` reaches the method this pattern is the
+    /// address of. As the OSC specification intends, the message address may be a
+    /// pattern itself when the method address is literal.
+    pub fn routes(&self, address: &str) -> bool {
+        if self.matches(address) {
+            return true;
+        }
+        self.is_literal() && AddressPattern::from_str(address).is_ok_and(|incoming| incoming.matches(&self.to_string()))
+    }
+
+    /// The packet with only the messages matching the pattern, also inside
+    /// bundles. None when nothing matches.
+    pub fn filter_packet(&self, packet: OscPacket) -> Option<OscPacket> {
+        match packet {
+            OscPacket::Message(msg) => self.matches(&msg.addr).then_some(OscPacket::Message(msg)),
+            OscPacket::Bundle(mut bundle) => {
+                bundle.content = bundle.content.into_iter().filter_map(|p| self.filter_packet(p)).collect();
+                (!bundle.content.is_empty()).then_some(OscPacket::Bundle(bundle))
+            }
+        }
+    }
+}
+
+fn match_pattern(pattern: &[char], address: &[char]) -> bool {
+    let part_char = |index: usize| address.get(index).filter(|c| **c != '/');
+    match pattern.first() {
+        None => address.is_empty(),
+        Some('*') => {
+            for skip in 0..=address.len() {
+                if match_pattern(&pattern[1..], &address[skip..]) {
+                    return true;
+                }
+                if part_char(skip).is_none() {
+                    return false;
+                }
+            }
+            false
+        }
+        Some('?') => part_char(0).is_some() && match_pattern(&pattern[1..], &address[1..]),
+        Some('[') => {
+            let end = pattern.iter().position(|c| *c == ']').unwrap_or(pattern.len() - 1);
+            part_char(0).is_some_and(|c| in_set(&pattern[1..end], *c)) && match_pattern(&pattern[end + 1..], &address[1..])
+        }
+        Some('{') => {
+            let end = pattern.iter().position(|c| *c == '}').unwrap_or(pattern.len() - 1);
+            pattern[1..end].split(|c| *c == ',').any(|alternative| {
+                address.starts_with(alternative) && match_pattern(&pattern[end + 1..], &address[alternative.len()..])
+            })
+        }
+        Some(c) => address.first() == Some(c) && match_pattern(&pattern[1..], &address[1..]),
+    }
+}
+
+/// Whether `c` is in a bracket set like `a-z0`; a leading `!` negates the set and
+/// a `-` at either end stands for itself.
+fn in_set(set: &[char], c: char) -> bool {
+    let (negate, set) = match set.first() {
+        Some('!') => (true, &set[1..]),
+        _ => (false, set),
+    };
+    let mut found = false;
+    let mut index = 0;
+    while index < set.len() {
+        if index + 2 < set.len() && set[index + 1] == '-' {
+            let (low, high) = (set[index].min(set[index + 2]), set[index].max(set[index + 2]));
+            found |= (low..=high).contains(&c);
+            index += 3;
+        } else {
+            found |= set[index] == c;
+            index += 1;
+        }
+    }
+    found != negate
+}
+
+/// What the thread reading a TCP connection passes to the server.
+enum TcpEvent {
+    Frame(Vec<u8>, SocketAddr),
+    Closed(SocketAddr),
+}
+
+/// Reads the packets of one TCP connection until it closes or `running` is cleared.
+fn read_tcp_packets(
+    stream: TcpStream,
+    from_addr: SocketAddr,
+    framing: Framing,
+    packets: Sender<TcpEvent>,
+    running: Arc<AtomicBool>,
+) {
+    read_tcp_frames(stream, from_addr, framing, &packets, running);
+    eprintln!("Connection from {} closed", from_addr);
+    let _ = packets.send(TcpEvent::Closed(from_addr));
+}
+
+fn read_tcp_frames(
+    mut stream: TcpStream,
+    from_addr: SocketAddr,
+    framing: Framing,
+    packets: &Sender<TcpEvent>,
+    running: Arc<AtomicBool>,
+) {
+    eprintln!("Connection from {}", from_addr);
+    if stream.set_nonblocking(false).and_then(|_| stream.set_read_timeout(Some(Duration::from_millis(100)))).is_err() {
+        return;
+    }
+    let mut decoder = FrameDecoder::new(framing);
+    let mut buf = [0u8; 4096];
+    while running.load(Ordering::SeqCst) {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(size) => {
+                decoder.push(&buf[..size]);
+                loop {
+                    match decoder.next_frame() {
+                        Ok(Some(frame)) => {
+                            if packets.send(TcpEvent::Frame(frame, from_addr)).is_err() {
+                                return;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("Closing connection from {}: {}", from_addr, e);
+                            return;
+                        }
+                    }
+                }
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                eprintln!("Error reading from {}: {}", from_addr, e);
+                break;
+            }
+        }
+    }
+}
+
+/// Sends packets back over the socket or connection a server received on. Replies
+/// then come from the address the sender contacted, which NATs and stateful
+/// firewalls require.
+#[derive(Default)]
+pub struct Replies {
+    udp: Option<UdpSocket>,
+    tcp: Option<(Framing, HashMap<SocketAddr, TcpStream>)>,
+}
+
+impl Replies {
+    pub fn send(&mut self, packet: &OscPacket, to_addr: SocketAddr) {
+        let buf = match encoder::encode(packet) {
+            Ok(buf) => buf,
+            Err(e) => {
+                eprintln!("Could not encode OSC packet: {}", e);
+                return;
+            }
+        };
+        let result = match (&self.udp, &mut self.tcp) {
+            (Some(sock), _) => sock.send_to(&buf, to_addr).map(|_| ()),
+            (None, Some((framing, connections))) => match connections.get_mut(&to_addr) {
+                Some(stream) => stream.write_all(&framing.encode(&buf)),
+                None => Err(io::Error::new(io::ErrorKind::NotConnected, "connection closed")),
+            },
+            (None, None) => Err(io::Error::new(io::ErrorKind::NotConnected, "server is not listening")),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not reply to {}: {}", to_addr, e);
+        }
+    }
+}
+
+/// Binds to the first of the resolved addresses which works. A name can resolve
+/// to addresses which are not usable here, e.g. a link-local IPv6 address.
+fn bind_first<T>(addresses: &[SocketAddr], bind: impl Fn(SocketAddr) -> io::Result<T>) -> Result<(T, SocketAddr), String> {
+    let mut errors = Vec::new();
+    for &addr in addresses {
+        match bind(addr) {
+            Ok(bound) => return Ok((bound, addr)),
+            Err(e) => errors.push(format!("{}: {}", addr, e)),
+        }
+    }
+    if errors.is_empty() {
+        return Err(String::from("no addresses"));
     }
+    Err(errors.join(", "))
 }
 
+/// Handles a packet and the address it came from, and may reply to it. Returning
+/// anything but 0 stops the server.
+pub type PacketHandler = Box<dyn FnMut(OscPacket, SocketAddr, &mut Replies) -> u32>;
+
+/// Sees every received packet before it is decoded, malformed ones included.
+pub type RawHandler = Box<dyn FnMut(&[u8], SocketAddr)>;
+
+/// Bytes of a malformed packet shown in the log.
+const MALFORMED_BYTES_SHOWN: usize = 256;
+
 pub struct OscServer {
     sock_addr: String,
-    packet_handler: fn(OscPacket) -> u32,
+    packet_handler: PacketHandler,
+    raw_handler: Option<RawHandler>,
+    replies: Replies,
+    decode_errors: u64,
     pub last_returned: u32,
     pub is_listening: bool,
 }
 
 impl OscServer {
-    pub fn new(sock_addr: &str, packet_handler: fn(OscPacket) -> u32) -> OscServer {
+    pub fn new<F>(sock_addr: &str, mut packet_handler: F) -> OscServer
+    where
+        F: FnMut(OscPacket, SocketAddr) -> u32 + 'static,
+    {
+        OscServer::new_replying(sock_addr, move |packet, from_addr, _: &mut Replies| packet_handler(packet, from_addr))
+    }
+
+    /// A server whose handler can send replies from the address it listens on.
+    pub fn new_replying<F>(sock_addr: &str, packet_handler: F) -> OscServer
+    where
+        F: FnMut(OscPacket, SocketAddr, &mut Replies) -> u32 + 'static,
+    {
         OscServer {
             sock_addr: sock_addr.to_string(),
-            packet_handler: packet_handler,
+            packet_handler: Box::new(packet_handler),
+            raw_handler: None,
+            replies: Replies::default(),
+            decode_errors: 0,
             last_returned: 0,
             is_listening: false,
         }
     }
 
+    /// Also passes the raw bytes of each packet to `raw_handler`, before decoding.
+    pub fn with_raw_handler<F>(mut self, raw_handler: F) -> OscServer
+    where
+        F: FnMut(&[u8], SocketAddr) + 'static,
+    {
+        self.raw_handler = Some(Box::new(raw_handler));
+        self
+    }
+
     // New method that accepts a running flag
     pub fn listen_with_interrupt(&mut self, sender: &Sender<u32>, running: Arc<AtomicBool>) -> u32 {
         self.listen_and_stop_with_interrupt(-1, sender, running)
@@ -70,17 +649,31 @@ impl OscServer {
     ) -> u32 {
         self.is_listening = true;
 
-        let addr = match SocketAddrV4::from_str(&self.sock_addr.to_string()) {
-            Ok(addr) => addr,
-            Err(_) => panic!("Invalid OSC ip address addr"),
+        let url = match OscUrl::from_str(&self.sock_addr) {
+            Ok(url) => url,
+            Err(e) => return self.fail_to_listen(e),
         };
+        let addresses = match url.address.resolve() {
+            Ok(addresses) => addresses,
+            Err(e) => return self.fail_to_listen(format!("Can not listen to {}: {}", url, e)),
+        };
+        if let OscTransport::Tcp(_) = url.transport {
+            return self.listen_tcp(&url, &addresses, max_nr_of_messages, sender, running);
+        }
 
-        let sock = UdpSocket::bind(addr).unwrap();
-        
         // Set socket to non-blocking with a timeout
-        sock.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
-        
-        println!("Listening to {}", addr);
+        let bind = |addr| {
+            let sock = UdpSocket::bind(addr)?;
+            sock.set_read_timeout(Some(Duration::from_millis(100)))?;
+            Ok(sock)
+        };
+        let (sock, addr) = match bind_first(&addresses, bind) {
+            Ok(bound) => bound,
+            Err(e) => return self.fail_to_listen(format!("Can not listen to {}: {}", url, e)),
+        };
+
+        eprintln!("Listening to {}", addr);
+        self.replies = Replies { udp: sock.try_clone().ok(), tcp: None };
 
         let mut buf = [0u8; rosc::decoder::MTU];
         let mut return_value: u32 = 0;
@@ -90,22 +683,23 @@ impl OscServer {
               (max_nr_of_messages == -1 || msg_counter < max_nr_of_messages) {
             
             match sock.recv_from(&mut buf) {
-                Ok((size, _addr)) => {
+                Ok((size, from_addr)) => {
                     // Check running flag again before processing
                     if !running.load(Ordering::SeqCst) {
                         break;
                     }
 
-                    let (_i, packet) = rosc::decoder::decode_udp(&buf[..size]).unwrap();
-                    return_value = (self.packet_handler)(packet);
-                    self.last_returned = return_value;
+                    return_value = match self.handle_bytes(&buf[..size], from_addr) {
+                        Some(return_value) => return_value,
+                        None => continue,
+                    };
                     
                     if sender.send(return_value).is_err() {
                         // Channel closed, exit gracefully
                         break;
                     }
                     
-                    msg_counter = msg_counter + 1;
+                    msg_counter += 1;
                     
                     // If handler returns non-zero, it might signal to stop
                     if return_value != 0 {
@@ -119,7 +713,7 @@ impl OscServer {
                             continue;
                         }
                         _ => {
-                            println!("Error receiving from socket: {}", e);
+                            eprintln!("Error receiving from socket: {}", e);
                             break;
                         }
                     }
@@ -127,19 +721,165 @@ impl OscServer {
             }
         }
 
+        self.stop_listening();
+        return_value
+    }
+
+    /// Decodes and handles received bytes. Malformed packets are logged and counted
+    /// instead of handled, since any device on the network can send them.
+    fn handle_bytes(&mut self, bytes: &[u8], from_addr: SocketAddr) -> Option<u32> {
+        if let Some(raw_handler) = self.raw_handler.as_mut() {
+            raw_handler(bytes, from_addr);
+        }
+        match rosc::decoder::decode_udp(bytes) {
+            Ok((_, packet)) => {
+                self.last_returned = (self.packet_handler)(packet, from_addr, &mut self.replies);
+                Some(self.last_returned)
+            }
+            Err(e) => {
+                self.decode_errors += 1;
+                eprintln!(
+                    "Malformed OSC packet from {} ({} bytes, {} malformed so far): {}",
+                    from_addr, bytes.len(), self.decode_errors, e
+                );
+                let shown = &bytes[..bytes.len().min(MALFORMED_BYTES_SHOWN)];
+                eprint!("{}", output_format::hex_dump(shown));
+                if bytes.len() > shown.len() {
+                    eprintln!("... {} more bytes", bytes.len() - shown.len());
+                }
+                None
+            }
+        }
+    }
+
+    fn fail_to_listen(&mut self, error: String) -> u32 {
+        eprintln!("{}", error);
+        self.is_listening = false;
+        0
+    }
+
+    fn stop_listening(&mut self) {
         self.is_listening = false;
-        println!("OSC Server stopped gracefully");
+        self.replies = Replies::default();
+        if self.decode_errors > 0 {
+            eprintln!("OSC Server stopped gracefully, {} malformed packets ignored", self.decode_errors);
+        } else {
+            eprintln!("OSC Server stopped gracefully");
+        }
+    }
+
+    /// Accepts TCP connections and handles the packets of all of them, in the order
+    /// they arrive. Each connection is read in its own thread.
+    fn listen_tcp(
+        &mut self,
+        url: &OscUrl,
+        addresses: &[SocketAddr],
+        max_nr_of_messages: i32,
+        sender: &Sender<u32>,
+        running: Arc<AtomicBool>,
+    ) -> u32 {
+        let bind = |addr| {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        };
+        let (listener, addr) = match bind_first(addresses, bind) {
+            Ok(bound) => bound,
+            Err(e) => return self.fail_to_listen(format!("Can not listen to {}: {}", url, e)),
+        };
+        let framing = match url.transport {
+            OscTransport::Tcp(framing) => framing,
+            OscTransport::Udp => unreachable!("UDP is not listened to over TCP"),
+        };
+        eprintln!("Listening to {}://{}", url.scheme(), addr);
+
+        let (packet_sender, packets) = mpsc::channel::<TcpEvent>();
+        self.replies = Replies { udp: None, tcp: Some((framing, HashMap::new())) };
+        let mut return_value: u32 = 0;
+        let mut msg_counter = 0;
+
+        while running.load(Ordering::SeqCst) &&
+              (max_nr_of_messages == -1 || msg_counter < max_nr_of_messages) {
+            loop {
+                match listener.accept() {
+                    Ok((stream, from_addr)) => {
+                        if let (Ok(writer), Some((_, connections))) = (stream.try_clone(), &mut self.replies.tcp) {
+                            connections.insert(from_addr, writer);
+                        }
+                        let packet_sender = packet_sender.clone();
+                        let running = running.clone();
+                        thread::spawn(move || read_tcp_packets(stream, from_addr, framing, packet_sender, running));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        eprintln!("Error accepting connection: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            match packets.recv_timeout(Duration::from_millis(100)) {
+                Ok(TcpEvent::Closed(from_addr)) => {
+                    if let Some((_, connections)) = &mut self.replies.tcp {
+                        connections.remove(&from_addr);
+                    }
+                }
+                Ok(TcpEvent::Frame(bytes, from_addr)) => {
+                    return_value = match self.handle_bytes(&bytes, from_addr) {
+                        Some(return_value) => return_value,
+                        None => continue,
+                    };
+                    if sender.send(return_value).is_err() {
+                        break;
+                    }
+                    msg_counter += 1;
+                    if return_value != 0 {
+                        break;
+                    }
+                }
+                Err(_) => continue,
+            }
+        }
+
+        self.stop_listening();
         return_value
     }
+}
+
+/// Prints received packets, optionally only the messages matching a pattern.
+pub struct OscEcho {
+    format: OutputFormat,
+    filter: Option<AddressPattern>,
+}
+
+impl OscEcho {
+    /// Prints in `format`; CSV output starts with a header line.
+    pub fn new(format: OutputFormat, filter: Option<AddressPattern>) -> OscEcho {
+        if format == OutputFormat::Csv {
+            println!("{}", output_format::OSC_CSV_HEADER);
+        }
+        OscEcho { format, filter }
+    }
+
+    pub fn echo_osc_packet(&mut self, packet: OscPacket, from_addr: SocketAddr) -> u32 {
+        let packet = match &self.filter {
+            Some(pattern) => match pattern.filter_packet(packet) {
+                Some(packet) => packet,
+                None => return 0,
+            },
+            None => packet,
+        };
+        if self.format != OutputFormat::Text {
+            let time = output_format::now_seconds();
+            for record in output_format::osc_records(self.format, time, &from_addr.to_string(), &packet) {
+                println!("{}", record);
+            }
+            return 0;
+        }
 
-    pub fn echo_osc_packet(packet: OscPacket) -> u32 {
         match packet {
             OscPacket::Message(msg) => {
-                match msg.addr.as_str() {
-                    _ => {
-                        println!("msg: {:?}", msg);
-                    }
-                }
+                println!("msg: {:?}", msg);
             }
 
             OscPacket::Bundle(bundle) => {
@@ -150,3 +890,253 @@ impl OscServer {
         0
     }
 }
+
+/// Sends every packet back to where it came from, unchanged, from the address it
+/// was sent to.
+#[derive(Default)]
+pub struct OscReflector;
+
+impl OscReflector {
+    pub fn new() -> OscReflector {
+        OscReflector
+    }
+
+    pub fn reflect_osc_packet(&mut self, packet: OscPacket, from_addr: SocketAddr, replies: &mut Replies) -> u32 {
+        replies.send(&packet, from_addr);
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_osc_url() {
+        let url = OscUrl::from_str("127.0.0.1:5566").unwrap();
+        assert_eq!(url.transport, OscTransport::Udp);
+        assert_eq!(url.to_string(), "udp://127.0.0.1:5566");
+        assert_eq!(OscUrl::from_str("tcp://127.0.0.1:5566").unwrap().transport, OscTransport::Tcp(Framing::LengthPrefix));
+        assert_eq!(OscUrl::from_str("TCP+SLIP://127.0.0.1:5566").unwrap().transport, OscTransport::Tcp(Framing::Slip));
+        assert!(OscUrl::from_str("http://127.0.0.1:5566").is_err());
+        assert!(OscUrl::from_str("tcp://127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_length_prefix_frames() {
+        let mut decoder = FrameDecoder::new(Framing::LengthPrefix);
+        let mut stream = Framing::LengthPrefix.encode(b"/a\0\0");
+        stream.extend(Framing::LengthPrefix.encode(b"/bc\0"));
+        assert_eq!(stream[..4], [0, 0, 0, 4]);
+
+        // Bytes arrive in arbitrary pieces
+        decoder.push(&stream[..6]);
+        assert_eq!(decoder.next_frame(), Ok(None));
+        decoder.push(&stream[6..]);
+        assert_eq!(decoder.next_frame(), Ok(Some(b"/a\0\0".to_vec())));
+        assert_eq!(decoder.next_frame(), Ok(Some(b"/bc\0".to_vec())));
+        assert_eq!(decoder.next_frame(), Ok(None));
+
+        decoder.push(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn test_slip_frames() {
+        let packet = [0x2F, SLIP_END, 0x01, SLIP_ESC, 0x02];
+        let frame = Framing::Slip.encode(&packet);
+        assert_eq!(frame, vec![SLIP_END, 0x2F, SLIP_ESC, SLIP_ESC_END, 0x01, SLIP_ESC, SLIP_ESC_ESC, 0x02, SLIP_END]);
+
+        let mut decoder = FrameDecoder::new(Framing::Slip);
+        decoder.push(&frame[..3]);
+        assert_eq!(decoder.next_frame(), Ok(None));
+        decoder.push(&frame[3..]);
+        decoder.push(&Framing::Slip.encode(b"/x"));
+        assert_eq!(decoder.next_frame(), Ok(Some(packet.to_vec())));
+        assert_eq!(decoder.next_frame(), Ok(Some(b"/x".to_vec())));
+        assert_eq!(decoder.next_frame(), Ok(None));
+    }
+
+    fn matches(pattern: &str, address: &str) -> bool {
+        AddressPattern::from_str(pattern).unwrap().matches(address)
+    }
+
+    #[test]
+    fn test_address_pattern_wildcards() {
+        assert!(matches("/foo/bar", "/foo/bar"));
+        assert!(!matches("/foo/bar", "/foo/baz"));
+        assert!(matches("/foo/b?r", "/foo/bar"));
+        assert!(!matches("/foo?bar", "/foo/bar"));
+        assert!(matches("/foo/*", "/foo/bar"));
+        assert!(matches("/foo/*", "/foo/"));
+        assert!(!matches("/foo/*", "/foo/bar/baz"));
+        assert!(matches("/*/bar", "/foo/bar"));
+        assert!(matches("/*/*", "/foo/bar"));
+        assert!(matches("/foo*bar", "/foo123bar"));
+        assert!(matches("/fo*o", "/foooo"));
+        assert!(!matches("/foo*", "/foo/bar"));
+    }
+
+    #[test]
+    fn test_address_pattern_sets_and_alternatives() {
+        // Examples from the OSC 1.0 specification
+        assert!(matches("/synth/[1-3]", "/synth/2"));
+        assert!(!matches("/synth/[1-3]", "/synth/4"));
+        assert!(matches("/synth/[!1-3]", "/synth/4"));
+        assert!(!matches("/synth/[!1-3]", "/synth/1"));
+        assert!(matches("/[abc]", "/b"));
+        assert!(matches("/[a-]", "/-"));
+        assert!(matches("/[-a]", "/-"));
+        assert!(!matches("/[a-]", "/b"));
+        assert!(matches("/{foo,bar}/x", "/bar/x"));
+        assert!(!matches("/{foo,bar}/x", "/baz/x"));
+        assert!(matches("/{th,s}ing", "/sing"));
+        assert!(matches("/{f,fo}o", "/foo"));
+        assert!(matches("/mixer/ch/[0-9][0-9]/{mute,solo}", "/mixer/ch/07/solo"));
+    }
+
+    #[test]
+    fn test_address_pattern_parse_and_route() {
+        assert!(AddressPattern::from_str("foo").is_err());
+        assert!(AddressPattern::from_str("/foo[1-3").is_err());
+        assert!(AddressPattern::from_str("/{foo,bar").is_err());
+        assert!(AddressPattern::from_str("/foo]").is_err());
+        assert!(AddressPattern::from_str("/[a/b]").is_err());
+
+        let method = AddressPattern::from_str("/midi").unwrap();
+        assert!(method.is_literal());
+        assert!(method.routes("/midi"));
+        assert!(method.routes("/mid?"));
+        assert!(method.routes("/{midi,osc}"));
+        assert!(!method.routes("/osc"));
+        assert!(AddressPattern::from_str("/midi/*").unwrap().routes("/midi/1"));
+
+        let bundle = OscPacket::Bundle(rosc::OscBundle {
+            timetag: rosc::OscTime { seconds: 0, fractional: 1 },
+            content: vec![
+                OscPacket::Message(OscMessage { addr: "/a/1".to_string(), args: vec![] }),
+                OscPacket::Message(OscMessage { addr: "/b/1".to_string(), args: vec![] }),
+            ],
+        });
+        match AddressPattern::from_str("/a/*").unwrap().filter_packet(bundle.clone()) {
+            Some(OscPacket::Bundle(filtered)) => assert_eq!(filtered.content.len(), 1),
+            other => panic!("expected a bundle, got {:?}", other),
+        }
+        assert_eq!(AddressPattern::from_str("/c").unwrap().filter_packet(bundle), None);
+    }
+
+    #[test]
+    fn test_servers_with_own_state() {
+        let running = Arc::new(AtomicBool::new(true));
+        let servers: Vec<_> = ["127.0.0.1:17361", "127.0.0.1:17362"]
+            .into_iter()
+            .map(|address| {
+                let running = running.clone();
+                thread::spawn(move || {
+                    let (send, _receive) = mpsc::channel::<u32>();
+                    let mut received = Vec::new();
+                    let mut server = OscServer::new(address, move |packet, from_addr| {
+                        if let OscPacket::Message(msg) = packet {
+                            received.push((msg.addr, from_addr.ip()));
+                        }
+                        // Stop at the second message, with what was received
+                        if received.len() == 2 {
+                            assert!(received.iter().all(|(_, ip)| ip.is_loopback()));
+                            received.len() as u32 + received[0].0.len() as u32
+                        } else {
+                            0
+                        }
+                    });
+                    server.listen_with_interrupt(&send, running)
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(200));
+        let first = OscSender::new(String::from("127.0.0.1:17361"));
+        let second = OscSender::new(String::from("127.0.0.1:17362"));
+        for _ in 0..2 {
+            first.send(String::from("/a"), vec![]);
+            second.send(String::from("/bcd"), vec![]);
+        }
+        let returned: Vec<u32> = servers.into_iter().map(|server| server.join().unwrap()).collect();
+        assert_eq!(returned, vec![2 + 2, 2 + 4]);
+    }
+
+    #[test]
+    fn test_reflector_replies_from_listening_port() {
+        for address in ["127.0.0.1:17364", "tcp://127.0.0.1:17365"] {
+            let running = Arc::new(AtomicBool::new(true));
+            let server_running = running.clone();
+            let server = thread::spawn(move || {
+                let (send, _receive) = mpsc::channel::<u32>();
+                let mut reflector = OscReflector::new();
+                OscServer::new_replying(address, move |packet, from_addr, replies| reflector.reflect_osc_packet(packet, from_addr, replies))
+                    .listen_and_stop_with_interrupt(1, &send, server_running)
+            });
+            thread::sleep(Duration::from_millis(200));
+            let sender = OscSender::new(address.to_string());
+            sender.send(String::from("/ping"), vec![OscType::Int(1)]);
+            let (packet, from_addr) = sender.receive(Duration::from_secs(2)).expect("no reply");
+            assert_eq!(from_addr, SocketAddr::from(([127, 0, 0, 1], address.rsplit(':').next().unwrap().parse().unwrap())));
+            assert_eq!(packet, OscPacket::Message(OscMessage { addr: String::from("/ping"), args: vec![OscType::Int(1)] }));
+            running.store(false, Ordering::SeqCst);
+            server.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_listen_falls_back_and_does_not_panic() {
+        // 192.0.2.1 is a documentation address, never assigned to this host
+        let unusable = SocketAddr::from(([192, 0, 2, 1], 17366));
+        let usable = SocketAddr::from(([127, 0, 0, 1], 17366));
+        let (_, addr) = bind_first(&[unusable, usable], UdpSocket::bind).unwrap();
+        assert_eq!(addr, usable);
+        assert!(bind_first(&[unusable], UdpSocket::bind).unwrap_err().starts_with("192.0.2.1:17366: "));
+
+        let running = Arc::new(AtomicBool::new(true));
+        let (send, _receive) = mpsc::channel::<u32>();
+        for address in ["192.0.2.1:17367", "tcp://192.0.2.1:17367", "not an address"] {
+            let mut server = OscServer::new(address, |_, _| 1);
+            assert_eq!(server.listen_with_interrupt(&send, running.clone()), 0);
+            assert!(!server.is_listening);
+        }
+    }
+
+    #[test]
+    fn test_malformed_packets_are_skipped() {
+        let running = Arc::new(AtomicBool::new(true));
+        let server_running = running.clone();
+        let server = thread::spawn(move || {
+            let (send, _receive) = mpsc::channel::<u32>();
+            let mut server = OscServer::new("127.0.0.1:17363", |_, _| 3);
+            let return_value = server.listen_and_stop_with_interrupt(1, &send, server_running);
+            (return_value, server.decode_errors)
+        });
+        thread::sleep(Duration::from_millis(200));
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for garbage in [&b"/no_type_tags"[..], &[0xFF, 0x00, 0x12], &b"#bundle\0"[..]] {
+            socket.send_to(garbage, "127.0.0.1:17363").unwrap();
+        }
+        OscSender::new(String::from("127.0.0.1:17363")).send(String::from("/ok"), vec![]);
+        let (return_value, decode_errors) = server.join().unwrap();
+        running.store(false, Ordering::SeqCst);
+        assert_eq!((return_value, decode_errors), (3, 3));
+    }
+
+    #[test]
+    fn test_tcp_round_trip() {
+        for url in ["tcp://127.0.0.1:17351", "tcp+slip://127.0.0.1:17352"] {
+            let running = Arc::new(AtomicBool::new(true));
+            let (send, receive) = mpsc::channel::<u32>();
+            let server_running = running.clone();
+            let server = thread::spawn(move || {
+                OscServer::new(url, |_, _| 7).listen_and_stop_with_interrupt(1, &send, server_running)
+            });
+            thread::sleep(Duration::from_millis(200));
+            OscSender::new(url.to_string()).send(String::from("/test"), vec![OscType::Int(1)]);
+            assert_eq!(receive.recv_timeout(Duration::from_secs(2)), Ok(7));
+            running.store(false, Ordering::SeqCst);
+            assert_eq!(server.join().unwrap(), 7);
+        }
+    }
+}
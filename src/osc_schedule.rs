@@ -0,0 +1,250 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+use rosc::{OscMessage, OscPacket, OscTime};
+
+/// Seconds between the NTP epoch (1900) of timetags and the UNIX epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// The timetag which means "as soon as possible". Zero is taken as immediate too.
+pub fn is_immediate(timetag: &OscTime) -> bool {
+    timetag.seconds == 0 && timetag.fractional <= 1
+}
+
+/// The time a timetag stands for; None for immediate timetags and times before
+/// 1970, which come from senders without a set clock.
+pub fn timetag_to_system_time(timetag: &OscTime) -> Option<SystemTime> {
+    if is_immediate(timetag) || (timetag.seconds as u64) < NTP_UNIX_OFFSET {
+        return None;
+    }
+    let nanos = ((timetag.fractional as u64 * 1_000_000_000) >> 32) as u32;
+    Some(SystemTime::UNIX_EPOCH + Duration::new(timetag.seconds as u64 - NTP_UNIX_OFFSET, nanos))
+}
+
+/// The timetag of a point in time, the inverse of `timetag_to_system_time`.
+pub fn system_time_to_timetag(time: SystemTime) -> OscTime {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    OscTime {
+        seconds: (since_epoch.as_secs() + NTP_UNIX_OFFSET) as u32,
+        fractional: (((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000) as u32,
+    }
+}
+
+/// The messages of a packet with the timetag they are due at, unpacking nested
+/// bundles. A message outside a bundle is immediate, as is one in an immediate
+/// bundle which is not inside a timed bundle.
+pub fn flatten_packet(packet: OscPacket) -> Vec<(OscMessage, Option<OscTime>)> {
+    fn collect(packet: OscPacket, timetag: Option<OscTime>, messages: &mut Vec<(OscMessage, Option<OscTime>)>) {
+        match packet {
+            OscPacket::Message(msg) => messages.push((msg, timetag)),
+            OscPacket::Bundle(bundle) => {
+                let timetag = if is_immediate(&bundle.timetag) { timetag } else { Some(bundle.timetag) };
+                for content in bundle.content {
+                    collect(content, timetag, messages);
+                }
+            }
+        }
+    }
+    let mut messages = Vec::new();
+    collect(packet, None, &mut messages);
+    messages
+}
+
+/// What to do with bundle contents which arrive after their timetag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LatePolicy {
+    /// Send right away.
+    Send,
+    /// Drop when later than the tolerance.
+    Drop,
+}
+
+impl FromStr for LatePolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<LatePolicy, String> {
+        match text.to_lowercase().as_str() {
+            "send" => Ok(LatePolicy::Send),
+            "drop" => Ok(LatePolicy::Drop),
+            _ => Err(String::from("Expects one of: send, drop")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispatch {
+    Now,
+    /// Due after the given time from now.
+    Later(Duration),
+    /// Dropped, being late by the given time.
+    Drop(Duration),
+}
+
+/// Decides when a message with `timetag` is sent.
+pub fn dispatch(timetag: Option<&OscTime>, now: SystemTime, policy: LatePolicy, tolerance: Duration) -> Dispatch {
+    let due = match timetag.and_then(timetag_to_system_time) {
+        Some(due) => due,
+        None => return Dispatch::Now,
+    };
+    match due.duration_since(now) {
+        Ok(wait) if wait.is_zero() => Dispatch::Now,
+        Ok(wait) => Dispatch::Later(wait),
+        Err(late) => {
+            let late = late.duration();
+            if policy == LatePolicy::Drop && late > tolerance {
+                Dispatch::Drop(late)
+            } else {
+                Dispatch::Now
+            }
+        }
+    }
+}
+
+/// Items waiting for their time. Items due at the same time come out in the order
+/// they were added.
+pub struct Schedule<T> {
+    heap: BinaryHeap<Reverse<(Instant, u64, Entry<T>)>>,
+    added: u64,
+}
+
+/// Wraps items so the heap orders by time and sequence only.
+struct Entry<T>(T);
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl<T> Default for Schedule<T> {
+    fn default() -> Self {
+        Schedule { heap: BinaryHeap::new(), added: 0 }
+    }
+}
+
+impl<T> Schedule<T> {
+    pub fn new() -> Schedule<T> {
+        Schedule::default()
+    }
+
+    pub fn push(&mut self, due: Instant, item: T) {
+        self.heap.push(Reverse((due, self.added, Entry(item))));
+        self.added += 1;
+    }
+
+    /// When the first item is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.heap.peek().map(|Reverse((due, _, _))| *due)
+    }
+
+    /// Takes the first item if it is due at `now`.
+    pub fn pop_due(&mut self, now: Instant) -> Option<T> {
+        if self.next_due()? > now {
+            return None;
+        }
+        self.heap.pop().map(|Reverse((_, _, Entry(item)))| item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscBundle;
+
+    fn msg(addr: &str) -> OscPacket {
+        OscPacket::Message(OscMessage { addr: addr.to_string(), args: vec![] })
+    }
+
+    fn time(seconds: u32) -> OscTime {
+        OscTime { seconds, fractional: 0 }
+    }
+
+    #[test]
+    fn test_timetag_to_system_time() {
+        assert_eq!(timetag_to_system_time(&OscTime { seconds: 0, fractional: 1 }), None);
+        assert_eq!(timetag_to_system_time(&time(1000)), None);
+        let half = OscTime { seconds: NTP_UNIX_OFFSET as u32 + 10, fractional: 1 << 31 };
+        assert_eq!(timetag_to_system_time(&half), Some(SystemTime::UNIX_EPOCH + Duration::from_millis(10_500)));
+        assert_eq!(system_time_to_timetag(SystemTime::UNIX_EPOCH + Duration::from_millis(10_500)), half);
+    }
+
+    #[test]
+    fn test_flatten_nested_bundles() {
+        let immediate = OscTime { seconds: 0, fractional: 1 };
+        let packet = OscPacket::Bundle(OscBundle {
+            timetag: time(4_000_000_000),
+            content: vec![
+                msg("/a"),
+                OscPacket::Bundle(OscBundle { timetag: time(4_000_000_001), content: vec![msg("/b")] }),
+                OscPacket::Bundle(OscBundle { timetag: immediate, content: vec![msg("/c")] }),
+            ],
+        });
+        let flat: Vec<(String, Option<OscTime>)> =
+            flatten_packet(packet).into_iter().map(|(m, t)| (m.addr, t)).collect();
+        assert_eq!(
+            flat,
+            vec![
+                ("/a".to_string(), Some(time(4_000_000_000))),
+                ("/b".to_string(), Some(time(4_000_000_001))),
+                ("/c".to_string(), Some(time(4_000_000_000))),
+            ]
+        );
+        assert_eq!(flatten_packet(msg("/d"))[0].1, None);
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let base = OscTime { seconds: NTP_UNIX_OFFSET as u32 + 100, fractional: 0 };
+        let at = |seconds: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        let tolerance = Duration::from_millis(10);
+        assert_eq!(dispatch(None, at(100), LatePolicy::Drop, tolerance), Dispatch::Now);
+        assert_eq!(dispatch(Some(&base), at(98), LatePolicy::Drop, tolerance), Dispatch::Later(Duration::from_secs(2)));
+        assert_eq!(dispatch(Some(&base), at(100), LatePolicy::Drop, tolerance), Dispatch::Now);
+        assert_eq!(dispatch(Some(&base), at(101), LatePolicy::Send, tolerance), Dispatch::Now);
+        assert_eq!(dispatch(Some(&base), at(101), LatePolicy::Drop, tolerance), Dispatch::Drop(Duration::from_secs(1)));
+        let slightly_late = SystemTime::UNIX_EPOCH + Duration::from_millis(100_005);
+        assert_eq!(dispatch(Some(&base), slightly_late, LatePolicy::Drop, tolerance), Dispatch::Now);
+    }
+
+    #[test]
+    fn test_schedule_order() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new();
+        schedule.push(start + Duration::from_millis(20), "late");
+        schedule.push(start + Duration::from_millis(10), "first");
+        schedule.push(start + Duration::from_millis(10), "second");
+        assert_eq!(schedule.next_due(), Some(start + Duration::from_millis(10)));
+        assert_eq!(schedule.pop_due(start), None);
+        let later = start + Duration::from_millis(15);
+        assert_eq!(schedule.pop_due(later), Some("first"));
+        assert_eq!(schedule.pop_due(later), Some("second"));
+        assert_eq!(schedule.pop_due(later), None);
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule.pop_due(start + Duration::from_millis(20)), Some("late"));
+        assert!(schedule.is_empty());
+    }
+}
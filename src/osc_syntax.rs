@@ -0,0 +1,622 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use rosc::{OscArray, OscBundle, OscColor, OscMessage, OscMidiMessage, OscPacket, OscTime, OscType};
+
+use crate::osc_schedule;
+
+/// A token of an `osc_send` line.
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// `[`, the start of an array.
+    Open,
+    /// `]`, the end of an array.
+    Close,
+    Word {
+        /// The type letter of a `d:` style prefix outside quotes.
+        prefix: Option<char>,
+        /// The text without prefix and quotes, with escapes resolved.
+        text: String,
+        /// Whether (part of) the text was quoted, which makes it a string.
+        quoted: bool,
+    },
+}
+
+/// Type letters which can prefix a value, as in `d:1.5`.
+const TYPE_PREFIXES: &str = "ifdhscbrmt";
+
+/// Splits a line on whitespace. Quoted parts may hold whitespace, brackets and
+/// escapes. The first token is the address and is kept whole, so patterns like
+/// `/synth/[1-3]` pass. In arguments a `[` opens an array at the start of a token
+/// only; a `]` closes one unless it matches a `[` within the same token.
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let is_address = tokens.is_empty();
+        if !is_address && (c == '[' || c == ']') {
+            chars.next();
+            tokens.push(if c == '[' { Token::Open } else { Token::Close });
+            continue;
+        }
+
+        let mut text = String::new();
+        let mut quoted = false;
+        let mut prefix = None;
+        let mut brackets = 0;
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || (c == ']' && brackets == 0 && !is_address) {
+                break;
+            }
+            chars.next();
+            match c {
+                '[' => {
+                    brackets += 1;
+                    text.push(c);
+                }
+                ']' => {
+                    brackets -= usize::from(brackets > 0);
+                    text.push(c);
+                }
+                '"' => {
+                    quoted = true;
+                    read_quoted(&mut chars, &mut text)?;
+                }
+                ':' if prefix.is_none() && !quoted && text.len() == 1 && TYPE_PREFIXES.contains(text.as_str()) => {
+                    prefix = text.pop();
+                }
+                c => text.push(c),
+            }
+        }
+        tokens.push(Token::Word { prefix, text, quoted });
+    }
+    Ok(tokens)
+}
+
+/// Reads a quoted string up to and including its closing quote.
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars>, text: &mut String) -> Result<(), String> {
+    loop {
+        match chars.next() {
+            None => return Err(String::from("Missing closing quote")),
+            Some('"') => return Ok(()),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('x') => {
+                        let hex: String = chars.by_ref().take(2).collect();
+                        let code = u8::from_str_radix(&hex, 16).map_err(|_| format!("Invalid escape \\x{}", hex))?;
+                        code as char
+                    }
+                    Some('u') => {
+                        if chars.next() != Some('{') {
+                            return Err(String::from("Invalid escape, expects \\u{HEX}"));
+                        }
+                        let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("Invalid escape \\u{{{}}}", hex))?
+                    }
+                    Some(other) => return Err(format!("Unknown escape \\{}", other)),
+                    None => return Err(String::from("Missing closing quote")),
+                };
+                text.push(escaped);
+            }
+            Some(c) => text.push(c),
+        }
+    }
+}
+
+fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.chars().filter(|c| *c != '_').collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits in '{}'", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("Invalid hex bytes '{}'", text)))
+        .collect()
+}
+
+fn parse_fixed_hex<const N: usize>(text: &str, what: &str) -> Result<[u8; N], String> {
+    let bytes = parse_hex_bytes(text)?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("Invalid {} '{}', expects {} hex bytes", what, text, N))
+}
+
+/// A timetag as `SECONDS` or `SECONDS:FRACTION` since 1900.
+fn parse_timetag(text: &str) -> Result<OscTime, String> {
+    let invalid = || format!("Invalid timetag '{}', expects SECONDS or SECONDS:FRACTION", text);
+    let (seconds, fractional) = text.split_once(':').unwrap_or((text, "0"));
+    Ok(OscTime {
+        seconds: seconds.parse().map_err(|_| invalid())?,
+        fractional: fractional.parse().map_err(|_| invalid())?,
+    })
+}
+
+/// A value with an explicit type.
+fn parse_typed(prefix: char, text: &str) -> Result<OscType, String> {
+    let invalid = |what: &str| format!("Invalid {} '{}'", what, text);
+    let value = match prefix {
+        'i' => OscType::Int(text.parse().map_err(|_| invalid("int32"))?),
+        'f' => OscType::Float(text.parse().map_err(|_| invalid("float32"))?),
+        'd' => OscType::Double(text.parse().map_err(|_| invalid("double"))?),
+        'h' => OscType::Long(text.parse().map_err(|_| invalid("int64"))?),
+        's' => OscType::String(text.to_string()),
+        'c' => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => OscType::Char(c),
+                _ => return Err(invalid("char, expects one character")),
+            }
+        }
+        'b' => OscType::Blob(parse_hex_bytes(text)?),
+        'r' => {
+            let [red, green, blue, alpha] = parse_fixed_hex::<4>(text, "RGBA color")?;
+            OscType::Color(OscColor { red, green, blue, alpha })
+        }
+        'm' => {
+            let [port, status, data1, data2] = parse_fixed_hex::<4>(text, "MIDI message")?;
+            OscType::Midi(OscMidiMessage { port, status, data1, data2 })
+        }
+        't' => OscType::Time(parse_timetag(text)?),
+        other => return Err(format!("Unknown type prefix '{}:'", other)),
+    };
+    Ok(value)
+}
+
+/// A value without prefix: `T`, `F`, `N` and `I` are true, false, nil and
+/// impulse, `1.5d` is a double and `123h` an int64. Other values are an int32, a
+/// float32 or else a string, as `osc_send` always did.
+fn parse_untyped(text: &str) -> OscType {
+    match text {
+        "T" => return OscType::Bool(true),
+        "F" => return OscType::Bool(false),
+        "N" => return OscType::Nil,
+        "I" => return OscType::Inf,
+        _ => {}
+    }
+    if let Ok(int) = text.parse::<i32>() {
+        return OscType::Int(int);
+    }
+    if let Ok(float) = text.parse::<f32>() {
+        return OscType::Float(float);
+    }
+    if let Some(Ok(double)) = text.strip_suffix('d').map(str::parse::<f64>) {
+        return OscType::Double(double);
+    }
+    if let Some(Ok(long)) = text.strip_suffix('h').map(str::parse::<i64>) {
+        return OscType::Long(long);
+    }
+    OscType::String(text.to_string())
+}
+
+fn parse_values(tokens: &mut std::vec::IntoIter<Token>, in_array: bool) -> Result<Vec<OscType>, String> {
+    let mut values = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Open => values.push(OscType::Array(OscArray { content: parse_values(tokens, true)? })),
+            Token::Close if in_array => return Ok(values),
+            Token::Close => return Err(String::from("Unexpected ']'")),
+            Token::Word { prefix: Some(prefix), text, .. } => values.push(parse_typed(prefix, &text)?),
+            Token::Word { text, quoted: true, .. } => values.push(OscType::String(text)),
+            Token::Word { text, .. } => values.push(parse_untyped(&text)),
+        }
+    }
+    if in_array {
+        return Err(String::from("Missing ']'"));
+    }
+    Ok(values)
+}
+
+/// Parses an OSC address followed by its arguments. The address gets a leading
+/// `/` when it lacks one. Returns None for an empty line.
+///
+/// Arguments are separated by whitespace and typed by a prefix: `i:` int32, `f:`
+/// float32, `d:` double, `h:` int64, `s:` string, `c:` char, `b:` blob in hex,
+/// `r:` RGBA color in hex, `m:` MIDI message in hex (port, status, data1, data2)
+/// and `t:` timetag. Strings in double quotes may contain whitespace and escapes
+/// like `\n`, `\"` or `\u{1F3B9}`, arrays are written as `[1 2 3]`.
+pub fn parse_message(line: &str) -> Result<Option<OscMessage>, String> {
+    let mut tokens = tokenize(line)?.into_iter();
+    let addr = match tokens.next() {
+        None => return Ok(None),
+        Some(Token::Word { text, prefix: None, .. }) if text.starts_with('/') => text,
+        Some(Token::Word { text, prefix: None, .. }) if !text.is_empty() => format!("/{}", text),
+        Some(_) => return Err(String::from("A message starts with its OSC address")),
+    };
+    let args = parse_values(&mut tokens, false)?;
+    Ok(Some(OscMessage { addr, args }))
+}
+
+/// Quotes a string with escapes, as `read_quoted` reads it.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Writes a value so `parse_message` reads it back as the same value.
+fn format_arg(arg: &OscType) -> String {
+    match arg {
+        OscType::Int(v) => v.to_string(),
+        OscType::Float(v) if v.to_string().parse::<i32>().is_ok() => format!("f:{}", v),
+        OscType::Float(v) => v.to_string(),
+        OscType::String(v) => {
+            let plain = !v.is_empty()
+                && !v.starts_with('[')
+                && !v.contains(|c: char| c.is_whitespace() || c.is_control() || c == '"' || c == ']')
+                && tokenize(&format!("/ {}", v)).is_ok_and(|tokens| matches!(&tokens[1..], [Token::Word { prefix: None, .. }]))
+                && parse_untyped(v) == *arg;
+            if plain { v.clone() } else { quote(v) }
+        }
+        OscType::Blob(v) => format!("b:{}", hex(v)),
+        OscType::Time(t) => format!("t:{}:{}", t.seconds, t.fractional),
+        OscType::Long(v) => format!("h:{}", v),
+        OscType::Double(v) => format!("d:{}", v),
+        OscType::Char(v) if v.is_whitespace() || v.is_control() || *v == '"' || *v == '[' || *v == ']' => format!("c:{}", quote(&v.to_string())),
+        OscType::Char(v) => format!("c:{}", v),
+        OscType::Color(c) => format!("r:{}", hex(&[c.red, c.green, c.blue, c.alpha])),
+        OscType::Midi(m) => format!("m:{}", hex(&[m.port, m.status, m.data1, m.data2])),
+        OscType::Bool(true) => String::from("T"),
+        OscType::Bool(false) => String::from("F"),
+        OscType::Nil => String::from("N"),
+        OscType::Inf => String::from("I"),
+        OscType::Array(array) => format!("[{}]", format_args(&array.content)),
+    }
+}
+
+/// Writes arguments in the syntax of `parse_message`, separated by spaces.
+pub fn format_args(args: &[OscType]) -> String {
+    args.iter().map(format_arg).collect::<Vec<_>>().join(" ")
+}
+
+/// Joins a message given as command line arguments into a line for
+/// `parse_message`. The shell already split the arguments, so one with whitespace
+/// is quoted to stay one string, unless it holds quotes or brackets itself, as in
+/// `'[1 2 3]'`. A type prefix is kept outside the quotes.
+pub fn join_args(args: &[String]) -> String {
+    let quote = |arg: &String| -> String {
+        if !arg.contains(char::is_whitespace) || arg.contains(['"', '[', ']']) {
+            return arg.clone();
+        }
+        let (prefix, text) = match arg.split_once(':') {
+            Some((prefix, text)) if prefix.len() == 1 && TYPE_PREFIXES.contains(prefix) => (&arg[..2], text),
+            _ => ("", arg.as_str()),
+        };
+        format!("{}\"{}\"", prefix, text.replace('\\', "\\\\"))
+    };
+    let mut args = args.iter();
+    let mut line = args.next().cloned().unwrap_or_default();
+    for arg in args {
+        line.push(' ');
+        line.push_str(&quote(arg));
+    }
+    line
+}
+
+/// When a bundle is due.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timetag {
+    /// `now`, or no timetag at all.
+    Immediate,
+    /// `+250ms` or `+1.5s`, counted from when the outermost bundle is complete.
+    After(Duration),
+    /// `SECONDS[:FRACTION]`, an NTP time.
+    At(OscTime),
+}
+
+impl FromStr for Timetag {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Timetag, String> {
+        if text == "now" {
+            return Ok(Timetag::Immediate);
+        }
+        let relative = match text.strip_prefix('+') {
+            Some(relative) => relative,
+            None => return parse_timetag(text).map(Timetag::At),
+        };
+        let invalid = || format!("Invalid relative time '{}', expects e.g. +250ms or +1.5s", text);
+        let seconds = match relative.strip_suffix("ms") {
+            Some(ms) => ms.parse::<f64>().map_err(|_| invalid())? / 1000.0,
+            None => relative.strip_suffix('s').ok_or_else(invalid)?.parse::<f64>().map_err(|_| invalid())?,
+        };
+        Duration::try_from_secs_f64(seconds).map(Timetag::After).map_err(|_| invalid())
+    }
+}
+
+impl Timetag {
+    pub fn to_osc_time(self, now: SystemTime) -> OscTime {
+        match self {
+            Timetag::Immediate => OscTime { seconds: 0, fractional: 1 },
+            Timetag::After(wait) => osc_schedule::system_time_to_timetag(now + wait),
+            Timetag::At(timetag) => timetag,
+        }
+    }
+}
+
+/// Content of a bundle which is not complete yet. Relative timetags are turned
+/// into times once the outermost bundle is complete.
+enum Pending {
+    Message(OscMessage),
+    Bundle(Timetag, Vec<Pending>),
+}
+
+impl Pending {
+    fn into_packet(self, now: SystemTime) -> OscPacket {
+        match self {
+            Pending::Message(msg) => OscPacket::Message(msg),
+            Pending::Bundle(timetag, content) => OscPacket::Bundle(OscBundle {
+                timetag: timetag.to_osc_time(now),
+                content: content.into_iter().map(|pending| pending.into_packet(now)).collect(),
+            }),
+        }
+    }
+}
+
+/// Turns lines into packets. A line `#bundle [TIMETAG]` opens a bundle, which may
+/// be nested, and `#end` closes it. The lines between are its messages.
+#[derive(Default)]
+pub struct PacketReader {
+    open_bundles: Vec<(Timetag, Vec<Pending>)>,
+}
+
+impl PacketReader {
+    pub fn new() -> PacketReader {
+        PacketReader::default()
+    }
+
+    /// Reads a line. Returns a packet for a message outside bundles and for the
+    /// outermost bundle once it is closed. A line with an error is left out.
+    pub fn read_line(&mut self, line: &str, now: SystemTime) -> Result<Option<OscPacket>, String> {
+        let mut tokens = tokenize(line)?.into_iter();
+        let pending = match tokens.next() {
+            Some(Token::Word { text, prefix: None, quoted: false }) if text == "#bundle" => {
+                let timetag = match (tokens.next(), tokens.next()) {
+                    (None, _) => Timetag::Immediate,
+                    (Some(Token::Word { text, prefix: None, quoted: false }), None) => text.parse()?,
+                    _ => return Err(String::from("Expects #bundle [now|+250ms|SECONDS:FRACTION]")),
+                };
+                self.open_bundles.push((timetag, Vec::new()));
+                return Ok(None);
+            }
+            Some(Token::Word { text, prefix: None, quoted: false }) if text == "#end" => {
+                if tokens.next().is_some() {
+                    return Err(String::from("#end takes no arguments"));
+                }
+                let (timetag, content) = self.open_bundles.pop().ok_or("#end without #bundle")?;
+                Pending::Bundle(timetag, content)
+            }
+            _ => match parse_message(line)? {
+                Some(msg) => Pending::Message(msg),
+                None => return Ok(None),
+            },
+        };
+        match self.open_bundles.last_mut() {
+            Some((_, content)) => {
+                content.push(pending);
+                Ok(None)
+            }
+            None => Ok(Some(pending.into_packet(now))),
+        }
+    }
+
+    /// The number of bundles which are not closed yet.
+    pub fn open_bundles(&self) -> usize {
+        self.open_bundles.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<OscType> {
+        parse_message(line).unwrap().unwrap().args
+    }
+
+    #[test]
+    fn test_inference_is_unchanged() {
+        let msg = parse_message("/test 12 15.2 str_arg").unwrap().unwrap();
+        assert_eq!(msg.addr, "/test");
+        assert_eq!(msg.args, vec![OscType::Int(12), OscType::Float(15.2), OscType::String("str_arg".to_string())]);
+        assert_eq!(parse_message("test").unwrap().unwrap().addr, "/test");
+        assert_eq!(parse_message("   ").unwrap(), None);
+        assert_eq!(args("/a 3000000000"), vec![OscType::Float(3e9)]);
+    }
+
+    #[test]
+    fn test_typed_values() {
+        assert_eq!(
+            args("/a i:1 f:2 d:1.5 h:123 s:12 c:x T F N I"),
+            vec![
+                OscType::Int(1),
+                OscType::Float(2.0),
+                OscType::Double(1.5),
+                OscType::Long(123),
+                OscType::String("12".to_string()),
+                OscType::Char('x'),
+                OscType::Bool(true),
+                OscType::Bool(false),
+                OscType::Nil,
+                OscType::Inf,
+            ]
+        );
+        assert_eq!(args("/a 1.5d 123h 2x"), vec![OscType::Double(1.5), OscType::Long(123), OscType::String("2x".to_string())]);
+        assert_eq!(
+            args("/a b:0A0B r:FF000080 m:00903C64 t:3900000000:2147483648"),
+            vec![
+                OscType::Blob(vec![0x0A, 0x0B]),
+                OscType::Color(OscColor { red: 255, green: 0, blue: 0, alpha: 128 }),
+                OscType::Midi(OscMidiMessage { port: 0, status: 0x90, data1: 60, data2: 100 }),
+                OscType::Time(OscTime { seconds: 3_900_000_000, fractional: 1 << 31 }),
+            ]
+        );
+        assert!(parse_message("/a i:1.5").is_err());
+        assert!(parse_message("/a b:0A0").is_err());
+        assert!(parse_message("/a r:FF00").is_err());
+        assert!(parse_message("/a c:xy").is_err());
+    }
+
+    #[test]
+    fn test_quoted_strings() {
+        assert_eq!(
+            args(r#"/a "hello world" "12" s:"T F" "tab\tquote\"\u{1F3B9}" """#),
+            vec![
+                OscType::String("hello world".to_string()),
+                OscType::String("12".to_string()),
+                OscType::String("T F".to_string()),
+                OscType::String("tab\tquote\"\u{1F3B9}".to_string()),
+                OscType::String(String::new()),
+            ]
+        );
+        // A prefix inside quotes is part of the string
+        assert_eq!(args(r#"/a "d:1""#), vec![OscType::String("d:1".to_string())]);
+        assert_eq!(args("/a x:1 http://host"), vec![OscType::String("x:1".to_string()), OscType::String("http://host".to_string())]);
+        assert!(parse_message(r#"/a "open"#).is_err());
+        assert!(parse_message(r#"/a "\q""#).is_err());
+    }
+
+    #[test]
+    fn test_arrays() {
+        let array = |content: Vec<OscType>| OscType::Array(OscArray { content });
+        assert_eq!(
+            args("/a [1 2 3] [] [x [d:1.5]] 4"),
+            vec![
+                array(vec![OscType::Int(1), OscType::Int(2), OscType::Int(3)]),
+                array(vec![]),
+                array(vec![OscType::String("x".to_string()), array(vec![OscType::Double(1.5)])]),
+                OscType::Int(4),
+            ]
+        );
+        assert_eq!(args(r#"/a ["[" "]"]"#), vec![array(vec![OscType::String("[".to_string()), OscType::String("]".to_string())])]);
+        assert!(parse_message("/a [1 2").is_err());
+        assert!(parse_message("/a 1]").is_err());
+        assert!(parse_message("/a x]").is_err());
+        assert_eq!(args("/a x[1] [y[2]]"), vec![OscType::String("x[1]".to_string()), array(vec![OscType::String("y[2]".to_string())])]);
+    }
+
+    #[test]
+    fn test_pattern_addresses() {
+        let msg = parse_message("/synth/[1-3] 5").unwrap().unwrap();
+        assert_eq!(msg.addr, "/synth/[1-3]");
+        assert_eq!(msg.args, vec![OscType::Int(5)]);
+        let msg = parse_message("/a/[!x]").unwrap().unwrap();
+        assert_eq!((msg.addr.as_str(), msg.args.len()), ("/a/[!x]", 0));
+        assert_eq!(parse_message("/x[1] [2]").unwrap().unwrap().addr, "/x[1]");
+        assert_eq!(parse_message("[1] 2").unwrap().unwrap().addr, "/[1]");
+    }
+
+    #[test]
+    fn test_timetags() {
+        assert_eq!(Timetag::from_str("now"), Ok(Timetag::Immediate));
+        assert_eq!(Timetag::from_str("+250ms"), Ok(Timetag::After(Duration::from_millis(250))));
+        assert_eq!(Timetag::from_str("+1.5s"), Ok(Timetag::After(Duration::from_millis(1500))));
+        assert_eq!(Timetag::from_str("3900000000:5"), Ok(Timetag::At(OscTime { seconds: 3_900_000_000, fractional: 5 })));
+        assert!(Timetag::from_str("+250").is_err());
+        assert!(Timetag::from_str("+-1s").is_err());
+        assert!(Timetag::from_str("soon").is_err());
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        let later = osc_schedule::timetag_to_system_time(&Timetag::After(Duration::from_millis(250)).to_osc_time(now));
+        assert_eq!(later, Some(now + Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn test_nested_bundles() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+        let mut reader = PacketReader::new();
+        let mut read = |line: &str| reader.read_line(line, now);
+        assert_eq!(read("/single 1"), Ok(Some(OscPacket::Message(OscMessage { addr: "/single".to_string(), args: vec![OscType::Int(1)] }))));
+        assert_eq!(read("#bundle"), Ok(None));
+        assert_eq!(read("/a"), Ok(None));
+        assert_eq!(read("#bundle +1s"), Ok(None));
+        assert!(read("/b \"open").is_err());
+        assert_eq!(read("/c"), Ok(None));
+        assert_eq!(read("#end"), Ok(None));
+        let packet = match read("#end") {
+            Ok(Some(OscPacket::Bundle(bundle))) => bundle,
+            other => panic!("expected a bundle, got {:?}", other),
+        };
+        assert!(osc_schedule::is_immediate(&packet.timetag));
+        assert_eq!(packet.content.len(), 2);
+        match &packet.content[1] {
+            OscPacket::Bundle(inner) => {
+                assert_eq!(inner.timetag, Timetag::After(Duration::from_secs(1)).to_osc_time(now));
+                assert_eq!(inner.content, vec![OscPacket::Message(OscMessage { addr: "/c".to_string(), args: vec![] })]);
+            }
+            other => panic!("expected a nested bundle, got {:?}", other),
+        }
+        assert!(reader.read_line("#end", now).is_err());
+        assert!(reader.read_line("#bundle 1 2", now).is_err());
+        assert_eq!(reader.open_bundles(), 0);
+    }
+
+    #[test]
+    fn test_join_args() {
+        let join = |args: &[&str]| join_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+        assert_eq!(join(&["/cue/go", "3", "-1.5"]), "/cue/go 3 -1.5");
+        assert_eq!(join(&["/a", "two words", "s:x y", "[1 2]", "\"q r\"", "a\\b c"]), r#"/a "two words" s:"x y" [1 2] "q r" "a\\b c""#);
+        assert_eq!(args(&join(&["/a", "two words", "d:1.5"])), vec![OscType::String("two words".to_string()), OscType::Double(1.5)]);
+        assert_eq!(parse_message(&join(&["/cue/go 3"])).unwrap().unwrap().args, vec![OscType::Int(3)]);
+    }
+
+    #[test]
+    fn test_format_args_round_trip() {
+        let array = |content: Vec<OscType>| OscType::Array(OscArray { content });
+        let values = vec![
+            OscType::Int(-3),
+            OscType::Float(2.0),
+            OscType::Float(1.5),
+            OscType::String("plain".to_string()),
+            OscType::String("12".to_string()),
+            OscType::String("T".to_string()),
+            OscType::String("d:1".to_string()),
+            OscType::String("a b, \"c\"\\\n\u{1}".to_string()),
+            OscType::String("[x".to_string()),
+            OscType::String(String::new()),
+            OscType::Blob(vec![0x0A, 0x0B]),
+            OscType::Time(OscTime { seconds: 3_900_000_000, fractional: 5 }),
+            OscType::Long(123),
+            OscType::Double(1.0),
+            OscType::Char(' '),
+            OscType::Char('x'),
+            OscType::Color(OscColor { red: 255, green: 0, blue: 0, alpha: 128 }),
+            OscType::Midi(OscMidiMessage { port: 0, status: 0x90, data1: 60, data2: 100 }),
+            OscType::Bool(true),
+            OscType::Nil,
+            OscType::Inf,
+            array(vec![OscType::Int(1), array(vec![OscType::String("x y".to_string())])]),
+        ];
+        let text = format_args(&values);
+        assert!(text.starts_with("-3 f:2 1.5 plain \"12\" \"T\" \"d:1\" "));
+        assert_eq!(args(&format!("/a {}", text)), values);
+        assert_eq!(format_args(&[]), "");
+    }
+}
@@ -0,0 +1,315 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rosc::{OscMessage, OscPacket, OscTime, OscType};
+
+use crate::midi_io::MidiMessage;
+use crate::osc_syntax;
+
+/// How the echo applications print received messages: the human readable text
+/// output, one JSON object per line, or CSV with a header line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<OutputFormat, String> {
+        match format.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(String::from("Expects one of: text, json, csv")),
+        }
+    }
+}
+
+pub const MIDI_CSV_HEADER: &str = "time,timestamp,source,type,channel,data1,data2,bytes";
+pub const OSC_CSV_HEADER: &str = "time,source,timetag,address,types,args";
+
+/// Seconds since the UNIX epoch, used as receive time of a message.
+pub fn now_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// Lines of 16 bytes with their offset, hex values and printable ASCII, each line
+/// ending in a newline. OSC pads to four bytes, so the columns are grouped by four.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex_column = String::new();
+        for (index, byte) in chunk.iter().enumerate() {
+            if index > 0 && index % 4 == 0 {
+                hex_column.push(' ');
+            }
+            hex_column.push_str(&format!("{:02X} ", byte));
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+            .collect();
+        dump.push_str(&format!("{:08x}  {:<51} |{}|\n", line * 16, hex_column, ascii));
+    }
+    dump
+}
+
+/// Formats a received MIDI message as a JSON or CSV record. Messages which do not
+/// decode get the type `Unknown`; the raw bytes are always included.
+pub fn midi_record(format: OutputFormat, time: f64, time_stamp: u64, source: &str, message: &[u8]) -> String {
+    let decoded = MidiMessage::parse(message).ok();
+    let name = decoded.as_ref().map_or("Unknown", |m| m.name());
+    let channel = decoded.as_ref().and_then(|m| m.channel());
+    let fields = decoded.as_ref().map(|m| m.fields()).unwrap_or_default();
+
+    match format {
+        OutputFormat::Json => {
+            let mut record = format!(
+                "{{\"time\":{:.6},\"timestamp\":{},\"source\":{},\"type\":{}",
+                time, time_stamp, json_string(source), json_string(name)
+            );
+            if let Some(channel) = channel {
+                record.push_str(&format!(",\"channel\":{}", channel + 1));
+            }
+            for (field, value) in &fields {
+                record.push_str(&format!(",\"{}\":{}", field, value));
+            }
+            let bytes: Vec<String> = message.iter().map(|b| b.to_string()).collect();
+            record.push_str(&format!(",\"bytes\":[{}]}}", bytes.join(",")));
+            record
+        }
+        _ => {
+            let field = |i: usize| fields.get(i).map(|(_, v)| v.to_string()).unwrap_or_default();
+            format!(
+                "{:.6},{},{},{},{},{},{},{}",
+                time,
+                time_stamp,
+                csv_field(source),
+                name,
+                channel.map(|c| (c + 1).to_string()).unwrap_or_default(),
+                field(0),
+                field(1),
+                hex(message)
+            )
+        }
+    }
+}
+
+fn osc_type_tag(arg: &OscType) -> String {
+    match arg {
+        OscType::Int(_) => String::from("i"),
+        OscType::Float(_) => String::from("f"),
+        OscType::String(_) => String::from("s"),
+        OscType::Blob(_) => String::from("b"),
+        OscType::Time(_) => String::from("t"),
+        OscType::Long(_) => String::from("h"),
+        OscType::Double(_) => String::from("d"),
+        OscType::Char(_) => String::from("c"),
+        OscType::Color(_) => String::from("r"),
+        OscType::Midi(_) => String::from("m"),
+        OscType::Bool(true) => String::from("T"),
+        OscType::Bool(false) => String::from("F"),
+        OscType::Array(array) => format!("[{}]", array.content.iter().map(osc_type_tag).collect::<String>()),
+        OscType::Nil => String::from("N"),
+        OscType::Inf => String::from("I"),
+    }
+}
+
+fn json_number<T: ToString>(value: T, finite: bool) -> String {
+    if finite {
+        value.to_string()
+    } else {
+        String::from("null")
+    }
+}
+
+fn osc_arg_json(arg: &OscType) -> String {
+    match arg {
+        OscType::Int(v) => v.to_string(),
+        OscType::Float(v) => json_number(v, v.is_finite()),
+        OscType::String(v) => json_string(v),
+        OscType::Blob(v) => json_string(&hex(v)),
+        OscType::Time(t) => format!("[{},{}]", t.seconds, t.fractional),
+        OscType::Long(v) => v.to_string(),
+        OscType::Double(v) => json_number(v, v.is_finite()),
+        OscType::Char(v) => json_string(&v.to_string()),
+        OscType::Color(c) => format!("[{},{},{},{}]", c.red, c.green, c.blue, c.alpha),
+        OscType::Midi(m) => format!("[{},{},{},{}]", m.port, m.status, m.data1, m.data2),
+        OscType::Bool(v) => v.to_string(),
+        OscType::Array(array) => format!("[{}]", array.content.iter().map(osc_arg_json).collect::<Vec<_>>().join(",")),
+        OscType::Nil | OscType::Inf => String::from("null"),
+    }
+}
+
+/// An NTP timetag in seconds since 1900.
+fn timetag_seconds(t: &OscTime) -> f64 {
+    t.seconds as f64 + t.fractional as f64 / 4294967296.0
+}
+
+fn osc_message_record(format: OutputFormat, time: f64, source: &str, timetag: Option<&OscTime>, msg: &OscMessage) -> String {
+    let types: String = msg.args.iter().map(osc_type_tag).collect();
+    match format {
+        OutputFormat::Json => {
+            let mut record = format!("{{\"time\":{:.6},\"source\":{}", time, json_string(source));
+            if let Some(t) = timetag {
+                record.push_str(&format!(",\"timetag\":{:.6}", timetag_seconds(t)));
+            }
+            let args: Vec<String> = msg.args.iter().map(osc_arg_json).collect();
+            record.push_str(&format!(
+                ",\"address\":{},\"types\":{},\"args\":[{}]}}",
+                json_string(&msg.addr),
+                json_string(&types),
+                args.join(",")
+            ));
+            record
+        }
+        _ => {
+            // All arguments go in one field, written as osc_send reads them
+            let fields = [
+                format!("{:.6}", time),
+                csv_field(source),
+                timetag.map(|t| format!("{:.6}", timetag_seconds(t))).unwrap_or_default(),
+                csv_field(&msg.addr),
+                csv_field(&types),
+                csv_field(&osc_syntax::format_args(&msg.args)),
+            ];
+            fields.join(",")
+        }
+    }
+}
+
+/// Formats a received OSC packet as JSON or CSV records, one per message. The
+/// messages of (nested) bundles are listed with the timetag of their bundle.
+pub fn osc_records(format: OutputFormat, time: f64, source: &str, packet: &OscPacket) -> Vec<String> {
+    fn collect(format: OutputFormat, time: f64, source: &str, timetag: Option<&OscTime>, packet: &OscPacket, records: &mut Vec<String>) {
+        match packet {
+            OscPacket::Message(msg) => records.push(osc_message_record(format, time, source, timetag, msg)),
+            OscPacket::Bundle(bundle) => {
+                for content in &bundle.content {
+                    collect(format, time, source, Some(&bundle.timetag), content, records);
+                }
+            }
+        }
+    }
+    let mut records = Vec::new();
+    collect(format, time, source, None, packet, &mut records);
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscBundle;
+
+    #[test]
+    fn test_escaping() {
+        assert_eq!(json_string("a \"b\"\n\\"), "\"a \\\"b\\\"\\n\\\\\"");
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"/test\0\0\0,i\0\0\0\0\0\x0c\xff");
+        assert_eq!(
+            dump,
+            "00000000  2F 74 65 73  74 00 00 00  2C 69 00 00  00 00 00 0C  |/test...,i......|\n\
+             00000010  FF                                                  |.|\n"
+        );
+        assert_eq!(hex_dump(&[]), "");
+    }
+
+    #[test]
+    fn test_midi_records() {
+        assert_eq!(
+            midi_record(OutputFormat::Json, 1.5, 42, "Teensy", &[0x92, 60, 100]),
+            "{\"time\":1.500000,\"timestamp\":42,\"source\":\"Teensy\",\"type\":\"NoteOn\",\"channel\":3,\"note\":60,\"velocity\":100,\"bytes\":[146,60,100]}"
+        );
+        assert_eq!(
+            midi_record(OutputFormat::Csv, 1.5, 42, "Teensy", &[0x92, 60, 100]),
+            "1.500000,42,Teensy,NoteOn,3,60,100,92 3C 64"
+        );
+        assert_eq!(
+            midi_record(OutputFormat::Csv, 1.5, 42, "Teensy", &[0xF8]),
+            "1.500000,42,Teensy,Clock,,,,F8"
+        );
+    }
+
+    #[test]
+    fn test_osc_records() {
+        let msg = OscMessage {
+            addr: String::from("/test"),
+            args: vec![OscType::Int(12), OscType::Float(15.5), OscType::String(String::from("a b"))],
+        };
+        let bundle = OscPacket::Bundle(OscBundle {
+            timetag: OscTime { seconds: 1, fractional: 1 << 31 },
+            content: vec![OscPacket::Message(msg.clone())],
+        });
+        assert_eq!(
+            osc_records(OutputFormat::Json, 1.0, "127.0.0.1:9000", &OscPacket::Message(msg)),
+            vec!["{\"time\":1.000000,\"source\":\"127.0.0.1:9000\",\"address\":\"/test\",\"types\":\"ifs\",\"args\":[12,15.5,\"a b\"]}"]
+        );
+        assert_eq!(
+            osc_records(OutputFormat::Csv, 1.0, "127.0.0.1:9000", &bundle),
+            vec!["1.000000,127.0.0.1:9000,1.500000,/test,ifs,\"12 15.5 \"\"a b\"\"\""]
+        );
+    }
+
+    /// The number of fields of a CSV line, minding quoted fields.
+    fn csv_columns(line: &str) -> usize {
+        let mut quoted = false;
+        1 + line.chars().filter(|c| {
+            if *c == '"' {
+                quoted = !quoted;
+            }
+            *c == ',' && !quoted
+        }).count()
+    }
+
+    #[test]
+    fn test_osc_csv_columns() {
+        let columns = csv_columns(OSC_CSV_HEADER);
+        for args in [vec![], vec![OscType::String(String::from("a,b"))], vec![OscType::Int(1), OscType::Nil, OscType::Float(2.5)]] {
+            let msg = OscPacket::Message(OscMessage { addr: String::from("/test"), args });
+            for record in osc_records(OutputFormat::Csv, 1.0, "127.0.0.1:9000", &msg) {
+                assert_eq!(csv_columns(&record), columns, "{}", record);
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::mdns_service_manager;
+
+/// How often names are looked up again, so a device which got a new address,
+/// e.g. from DHCP, is followed.
+const RERESOLVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait for an answer to an mDNS query for a `.local` name.
+const MDNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A host and port as given on the command line: `10.0.0.2:1234`, `[::1]:1234`,
+/// `[fe80::1%eth0]:1234` or a name like `mixer.local:10023`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostPort {
+    /// An IP address or name, without the brackets of IPv6 addresses.
+    pub host: String,
+    pub port: u16,
+}
+
+impl FromStr for HostPort {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<HostPort, String> {
+        let expected = || format!("Invalid address '{}', expects host:port, e.g. 127.0.0.1:1234, [::1]:1234 or mixer.local:1234", text);
+        let (host, port) = if let Some(rest) = text.strip_prefix('[') {
+            let (host, port) = rest.split_once("]:").ok_or_else(expected)?;
+            let address = host.split('%').next().unwrap_or_default();
+            if Ipv6Addr::from_str(address).is_err() || host.ends_with('%') {
+                return Err(format!("Invalid IPv6 address '{}'", host));
+            }
+            (host, port)
+        } else {
+            let (host, port) = text.rsplit_once(':').ok_or_else(expected)?;
+            if host.contains(':') {
+                return Err(format!("Invalid address '{}', IPv6 addresses are written in brackets, e.g. [::1]:1234", text));
+            }
+            let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_';
+            if host.is_empty() || !host.chars().all(valid_name) {
+                return Err(expected());
+            }
+            (host, port)
+        };
+        let port = port.parse::<u16>().map_err(|_| format!("Invalid port '{}', expects 0-65535", port))?;
+        Ok(HostPort { host: host.to_string(), port })
+    }
+}
+
+impl fmt::Display for HostPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl HostPort {
+    /// The address when the host is an IP address, which never has to be looked up.
+    /// Scopes of IPv6 addresses are only taken as interface index here.
+    pub fn literal(&self) -> Option<SocketAddr> {
+        if let Ok(ip) = IpAddr::from_str(&self.host) {
+            return Some(SocketAddr::new(ip, self.port));
+        }
+        SocketAddrV6::from_str(&format!("[{}]:{}", self.host, self.port)).ok().map(SocketAddr::V6)
+    }
+
+    /// Whether the host is a name or interface scope which has to be looked up.
+    pub fn needs_lookup(&self) -> bool {
+        self.literal().is_none()
+    }
+
+    fn is_mdns_name(&self) -> bool {
+        self.host.trim_end_matches('.').to_lowercase().ends_with(".local")
+    }
+
+    /// Looks up the addresses of the host with the system resolver. `.local` names
+    /// the system can not resolve are queried over mDNS.
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>, String> {
+        if let Some(address) = self.literal() {
+            return Ok(vec![address]);
+        }
+        let system = (self.host.as_str(), self.port).to_socket_addrs().map(|addresses| addresses.collect::<Vec<_>>());
+        match system {
+            Ok(addresses) if !addresses.is_empty() => Ok(addresses),
+            _ if self.is_mdns_name() => {
+                let hostname = format!("{}.", self.host.trim_end_matches('.'));
+                match mdns_service_manager::resolve_hostname(&hostname, self.port, MDNS_TIMEOUT) {
+                    Ok(addresses) if !addresses.is_empty() => Ok(addresses),
+                    Ok(_) => Err(format!("No answer for {} over mDNS", self.host)),
+                    Err(e) => Err(format!("Could not resolve {} over mDNS: {}", self.host, e)),
+                }
+            }
+            Ok(_) => Err(format!("No addresses found for {}", self.host)),
+            Err(e) => Err(format!("Could not resolve {}: {}", self.host, e)),
+        }
+    }
+}
+
+/// Chooses from the addresses of a name. The current address is kept as long as
+/// the name still has it, otherwise an address of the same family is preferred
+/// since a socket may be bound for it.
+pub fn pick(addresses: &[SocketAddr], current: Option<SocketAddr>) -> Option<SocketAddr> {
+    match current {
+        Some(current) if addresses.contains(&current) => Some(current),
+        Some(current) => addresses.iter().find(|a| a.is_ipv4() == current.is_ipv4()).or(addresses.first()).copied(),
+        None => addresses.first().copied(),
+    }
+}
+
+/// The address of a `HostPort`, kept up to date in a background thread when the
+/// host is a name. The thread ends when the `ResolvedAddress` is dropped.
+pub struct ResolvedAddress {
+    host_port: HostPort,
+    current: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl ResolvedAddress {
+    pub fn new(host_port: HostPort) -> ResolvedAddress {
+        let resolved = ResolvedAddress { host_port, current: Arc::new(Mutex::new(None)) };
+        if let Err(e) = resolved.refresh() {
+            eprintln!("{}, trying again every {}s", e, RERESOLVE_INTERVAL.as_secs());
+        }
+        if resolved.host_port.needs_lookup() {
+            let host_port = resolved.host_port.clone();
+            let current = Arc::downgrade(&resolved.current);
+            thread::spawn(move || reresolve(host_port, current));
+        }
+        resolved
+    }
+
+    /// The address to use now; None while the name did not resolve yet.
+    pub fn get(&self) -> Option<SocketAddr> {
+        *self.current.lock().unwrap()
+    }
+
+    /// Looks up the address now, e.g. after a connection failed.
+    pub fn refresh(&self) -> Result<SocketAddr, String> {
+        update(&self.host_port, &self.current)
+    }
+
+    pub fn host_port(&self) -> &HostPort {
+        &self.host_port
+    }
+}
+
+fn update(host_port: &HostPort, current: &Mutex<Option<SocketAddr>>) -> Result<SocketAddr, String> {
+    let addresses = host_port.resolve()?;
+    let mut current = current.lock().unwrap();
+    let address = pick(&addresses, *current).ok_or_else(|| format!("No addresses found for {}", host_port.host))?;
+    if current.is_some_and(|c| c != address) {
+        eprintln!("{} now resolves to {}", host_port, address);
+    }
+    *current = Some(address);
+    Ok(address)
+}
+
+fn reresolve(host_port: HostPort, current: Weak<Mutex<Option<SocketAddr>>>) {
+    loop {
+        thread::sleep(RERESOLVE_INTERVAL);
+        let current = match current.upgrade() {
+            Some(current) => current,
+            None => return,
+        };
+        let was_resolved = current.lock().unwrap().is_some();
+        match update(&host_port, &current) {
+            Ok(address) if !was_resolved => eprintln!("{} resolves to {}", host_port, address),
+            Ok(_) => {}
+            // Keep the last address, the name may come back
+            Err(e) if was_resolved => eprintln!("{}, keeping the last address", e),
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_host_port() {
+        let ipv4 = HostPort::from_str("127.0.0.1:5566").unwrap();
+        assert_eq!(ipv4.literal(), Some(SocketAddr::from(([127, 0, 0, 1], 5566))));
+        let ipv6 = HostPort::from_str("[::1]:5566").unwrap();
+        assert_eq!(ipv6.host, "::1");
+        assert_eq!(ipv6.to_string(), "[::1]:5566");
+        assert_eq!(ipv6.literal(), Some(SocketAddr::from((Ipv6Addr::LOCALHOST, 5566))));
+        let name = HostPort::from_str("mixer.local:10023").unwrap();
+        assert_eq!((name.host.as_str(), name.port), ("mixer.local", 10023));
+        assert!(name.needs_lookup());
+        assert!(name.is_mdns_name());
+
+        assert!(HostPort::from_str("::1:5566").is_err());
+        assert!(HostPort::from_str("[::1]").is_err());
+        assert!(HostPort::from_str("[::g]:5566").is_err());
+        assert!(HostPort::from_str("127.0.0.1").is_err());
+        assert!(HostPort::from_str("127.0.0.1:70000").is_err());
+        assert!(HostPort::from_str(":5566").is_err());
+        assert!(HostPort::from_str("my host:5566").is_err());
+    }
+
+    #[test]
+    fn test_scoped_ipv6() {
+        let indexed = HostPort::from_str("[fe80::1%2]:5566").unwrap();
+        assert_eq!(indexed.to_string(), "[fe80::1%2]:5566");
+        match indexed.literal() {
+            Some(SocketAddr::V6(address)) => assert_eq!(address.scope_id(), 2),
+            other => panic!("expected a scoped IPv6 address, got {:?}", other),
+        }
+        // Interface names are looked up by the system resolver
+        assert!(HostPort::from_str("[fe80::1%eth0]:5566").unwrap().needs_lookup());
+        assert!(HostPort::from_str("[fe80::1%]:5566").is_err());
+    }
+
+    #[test]
+    fn test_pick() {
+        let v4 = SocketAddr::from(([10, 0, 0, 2], 1234));
+        let other_v4 = SocketAddr::from(([10, 0, 0, 3], 1234));
+        let v6 = SocketAddr::from((Ipv6Addr::LOCALHOST, 1234));
+        assert_eq!(pick(&[v6, v4], None), Some(v6));
+        assert_eq!(pick(&[v6, v4], Some(v4)), Some(v4));
+        assert_eq!(pick(&[v6, other_v4], Some(v4)), Some(other_v4));
+        assert_eq!(pick(&[v6], Some(v4)), Some(v6));
+        assert_eq!(pick(&[], Some(v4)), None);
+    }
+
+    #[test]
+    fn test_resolve_localhost() {
+        let addresses = HostPort::from_str("localhost:5566").unwrap().resolve().unwrap();
+        assert!(addresses.iter().all(|a| a.ip().is_loopback() && a.port() == 5566));
+    }
+}
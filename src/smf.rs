@@ -0,0 +1,574 @@
+use std::collections::HashMap;
+
+/// Time division of a Standard MIDI File.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    /// Ticks per quarter note.
+    Metrical(u16),
+    /// SMPTE frames per second (29 means 29.97 drop frame) and ticks per frame.
+    Timecode { fps: u8, ticks_per_frame: u8 },
+}
+
+pub const META_TRACK_NAME: u8 = 0x03;
+pub const META_END_OF_TRACK: u8 = 0x2F;
+pub const META_TEMPO: u8 = 0x51;
+
+/// An event in a track. MIDI and SysEx events hold complete messages as they are
+/// sent to a port, SysEx including the leading 0xF0. Escape events (0xF7) hold
+/// bytes which are sent as is, e.g. the continuation of a SysEx message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind {
+    Midi(Vec<u8>),
+    SysEx(Vec<u8>),
+    Escape(Vec<u8>),
+    Meta(u8, Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackEvent {
+    /// Ticks since the previous event in the track.
+    pub delta: u32,
+    pub kind: EventKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Smf {
+    /// 0: a single track, 1: simultaneous tracks, 2: independent sequences.
+    pub format: u16,
+    pub timing: Timing,
+    pub tracks: Vec<Vec<TrackEvent>>,
+}
+
+/// How `Smf::from_recording` lays out a recording.
+pub struct RecordOptions {
+    pub ppq: u16,
+    pub bpm: f64,
+    pub format: u16,
+    /// Use a track per MIDI channel; implies format 1.
+    pub split_channels: bool,
+}
+
+/// How far, in microseconds, the time of a port may stray from the arrival time
+/// before the port is anchored to the arrival time again.
+const RESYNC_THRESHOLD: i64 = 100_000;
+
+/// Puts the timestamps of several input ports on one clock, in microseconds since
+/// the recording started. midir timestamps count from when each connection was
+/// made, so every port gets its own offset to the arrival time. A port whose time
+/// strays too far, e.g. because it reconnected and its timestamps restarted, is
+/// anchored again, so the time it was gone is kept as a gap.
+#[derive(Default)]
+pub struct RecordingClock {
+    /// Per port the offset from its timestamps and the last time given out.
+    ports: HashMap<String, (i64, u64)>,
+}
+
+impl RecordingClock {
+    pub fn new() -> RecordingClock {
+        RecordingClock::default()
+    }
+
+    /// The recording time of a message with `time_stamp` from port `source` which
+    /// arrived `arrival` microseconds after the recording started.
+    pub fn stamp(&mut self, source: &str, time_stamp: u64, arrival: u64) -> u64 {
+        let (time_stamp, arrival) = (time_stamp as i64, arrival as i64);
+        let (offset, last) = self.ports.entry(source.to_string()).or_insert((arrival - time_stamp, 0));
+        if (time_stamp + *offset - arrival).abs() > RESYNC_THRESHOLD {
+            *offset = arrival - time_stamp;
+        }
+        // Never back in time within a port
+        let time = ((time_stamp + *offset).max(0) as u64).max(*last);
+        *last = time;
+        time
+    }
+}
+
+fn write_vlq(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.reverse();
+    out.extend(bytes);
+}
+
+/// Default tempo of a file without tempo events: 120 BPM.
+const DEFAULT_MICROS_PER_QUARTER: u32 = 500_000;
+
+fn read_vlq(data: &[u8], position: &mut usize) -> Result<u32, String> {
+    let mut value: u32 = 0;
+    for _ in 0..4 {
+        let byte = *data.get(*position).ok_or("Unexpected end of track in variable length value")?;
+        *position += 1;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(String::from("Variable length value longer than four bytes"))
+}
+
+fn read_bytes<'a>(data: &'a [u8], position: &mut usize, length: usize) -> Result<&'a [u8], String> {
+    let bytes = data
+        .get(*position..*position + length)
+        .ok_or("Unexpected end of track")?;
+    *position += length;
+    Ok(bytes)
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<TrackEvent>, String> {
+    let mut events = Vec::new();
+    let mut position = 0;
+    let mut running_status: Option<u8> = None;
+
+    while position < data.len() {
+        let delta = read_vlq(data, &mut position)?;
+        let mut status = *data.get(position).ok_or("Unexpected end of track")?;
+        if status < 0x80 {
+            // Running status: the data bytes of a message reuse the previous status
+            status = running_status.ok_or("Data byte without running status")?;
+        } else {
+            position += 1;
+        }
+
+        let kind = match status {
+            0xFF => {
+                running_status = None;
+                let meta_type = read_bytes(data, &mut position, 1)?[0];
+                let length = read_vlq(data, &mut position)? as usize;
+                EventKind::Meta(meta_type, read_bytes(data, &mut position, length)?.to_vec())
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let length = read_vlq(data, &mut position)? as usize;
+                let bytes = read_bytes(data, &mut position, length)?;
+                if status == 0xF0 {
+                    let mut message = vec![0xF0];
+                    message.extend(bytes);
+                    EventKind::SysEx(message)
+                } else {
+                    EventKind::Escape(bytes.to_vec())
+                }
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let length = if (0xC0..=0xDF).contains(&status) { 1 } else { 2 };
+                let mut message = vec![status];
+                message.extend(read_bytes(data, &mut position, length)?);
+                EventKind::Midi(message)
+            }
+            _ => return Err(format!("Unexpected status byte {:02X} in track", status)),
+        };
+
+        let end_of_track = matches!(kind, EventKind::Meta(META_END_OF_TRACK, _));
+        events.push(TrackEvent { delta, kind });
+        if end_of_track {
+            break;
+        }
+    }
+    Ok(events)
+}
+
+/// The tempo is stored in three bytes of microseconds per quarter note.
+const MAX_MICROS_PER_QUARTER: u32 = 0xFF_FFFF;
+/// The slowest tempo a MIDI file can hold, about 3.58 BPM.
+pub const MIN_BPM: f64 = 60_000_000.0 / MAX_MICROS_PER_QUARTER as f64;
+/// The fastest tempo, one microsecond per quarter note.
+pub const MAX_BPM: f64 = 60_000_000.0;
+
+/// A tempo meta event. Tempos outside `MIN_BPM..=MAX_BPM` are clamped.
+fn tempo_event(bpm: f64) -> TrackEvent {
+    let micros_per_quarter = ((60_000_000.0 / bpm).round() as u32).clamp(1, MAX_MICROS_PER_QUARTER);
+    TrackEvent {
+        delta: 0,
+        kind: EventKind::Meta(META_TEMPO, micros_per_quarter.to_be_bytes()[1..].to_vec()),
+    }
+}
+
+fn track_name_event(name: &str) -> TrackEvent {
+    TrackEvent {
+        delta: 0,
+        kind: EventKind::Meta(META_TRACK_NAME, name.as_bytes().to_vec()),
+    }
+}
+
+/// Turns absolute ticks into delta times.
+fn to_track(mut events: Vec<(u64, EventKind)>, start: Vec<TrackEvent>) -> Vec<TrackEvent> {
+    events.sort_by_key(|(tick, _)| *tick);
+    let mut track = start;
+    let mut previous = 0;
+    for (tick, kind) in events {
+        track.push(TrackEvent {
+            delta: (tick - previous).min(0x0FFF_FFFF) as u32,
+            kind,
+        });
+        previous = tick;
+    }
+    track
+}
+
+impl Smf {
+    /// Parses a Standard MIDI File of any of the three formats. Chunks other than
+    /// tracks are skipped.
+    pub fn parse(bytes: &[u8]) -> Result<Smf, String> {
+        if bytes.len() < 14 || &bytes[0..4] != b"MThd" {
+            return Err(String::from("Not a Standard MIDI File: no MThd header"));
+        }
+        let header_length = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        if header_length < 6 {
+            return Err(String::from("MIDI file header too short"));
+        }
+        let format = u16::from_be_bytes([bytes[8], bytes[9]]);
+        if format > 2 {
+            return Err(format!("Unsupported MIDI file format {}", format));
+        }
+        let timing = if bytes[12] & 0x80 == 0 {
+            Timing::Metrical(u16::from_be_bytes([bytes[12], bytes[13]]))
+        } else {
+            Timing::Timecode {
+                fps: (bytes[12] as i8).wrapping_neg() as u8,
+                ticks_per_frame: bytes[13],
+            }
+        };
+        if timing == Timing::Metrical(0) {
+            return Err(String::from("MIDI file has zero ticks per quarter note"));
+        }
+
+        let mut tracks = Vec::new();
+        let mut position = 8 + header_length;
+        while position + 8 <= bytes.len() {
+            let chunk_type = &bytes[position..position + 4];
+            let length = u32::from_be_bytes([bytes[position + 4], bytes[position + 5], bytes[position + 6], bytes[position + 7]]) as usize;
+            let start = position + 8;
+            // Be lenient with a last chunk which is shorter than announced
+            let end = (start + length).min(bytes.len());
+            if chunk_type == b"MTrk" {
+                tracks.push(parse_track(&bytes[start..end]).map_err(|e| format!("Track {}: {}", tracks.len(), e))?);
+            }
+            position = end;
+        }
+
+        Ok(Smf { format, timing, tracks })
+    }
+
+    /// The messages to send with their time in microseconds since the start,
+    /// ordered by time. Tempo changes of all tracks apply to format 0 and 1 files;
+    /// the tracks of a format 2 file are independent and play one after the other.
+    pub fn timed_messages(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut messages = Vec::new();
+        if self.format == 2 {
+            let mut offset = 0;
+            for track in &self.tracks {
+                let (track_messages, length) = self.timed_tracks(std::slice::from_ref(track));
+                messages.extend(track_messages.into_iter().map(|(time, message)| (time + offset, message)));
+                offset += length;
+            }
+        } else {
+            messages = self.timed_tracks(&self.tracks).0;
+        }
+        messages
+    }
+
+    /// Merges simultaneous tracks; returns the timed messages and the total length.
+    fn timed_tracks(&self, tracks: &[Vec<TrackEvent>]) -> (Vec<(u64, Vec<u8>)>, u64) {
+        // Absolute ticks, ties ordered by track and position in the track
+        let mut events: Vec<(u64, usize, usize, &EventKind)> = Vec::new();
+        for (track_index, track) in tracks.iter().enumerate() {
+            let mut tick = 0u64;
+            for (event_index, event) in track.iter().enumerate() {
+                tick += event.delta as u64;
+                events.push((tick, track_index, event_index, &event.kind));
+            }
+        }
+        events.sort_by_key(|(tick, track_index, event_index, _)| (*tick, *track_index, *event_index));
+
+        let mut micros_per_quarter = DEFAULT_MICROS_PER_QUARTER as f64;
+        let mut last_tick = 0u64;
+        let mut time = 0.0f64;
+        let mut messages = Vec::new();
+        for (tick, _, _, kind) in events {
+            time += (tick - last_tick) as f64 * self.micros_per_tick(micros_per_quarter);
+            last_tick = tick;
+            match kind {
+                EventKind::Meta(META_TEMPO, data) if data.len() == 3 => {
+                    micros_per_quarter = u32::from_be_bytes([0, data[0], data[1], data[2]]) as f64;
+                }
+                EventKind::Midi(bytes) | EventKind::SysEx(bytes) | EventKind::Escape(bytes) => {
+                    messages.push((time.round() as u64, bytes.clone()));
+                }
+                EventKind::Meta(_, _) => {}
+            }
+        }
+        (messages, time.round() as u64)
+    }
+
+    fn micros_per_tick(&self, micros_per_quarter: f64) -> f64 {
+        match self.timing {
+            Timing::Metrical(ppq) => micros_per_quarter / ppq as f64,
+            Timing::Timecode { fps, ticks_per_frame } => {
+                let fps = if fps == 29 { 29.97 } else { fps as f64 };
+                1_000_000.0 / (fps * ticks_per_frame.max(1) as f64)
+            }
+        }
+    }
+
+    /// Lays out messages received at the given microsecond timestamps in a file
+    /// with the given tempo. The first message starts the file. Realtime and system
+    /// common messages cannot be stored in a file and are skipped.
+    pub fn from_recording(messages: &[(u64, Vec<u8>)], options: &RecordOptions) -> Smf {
+        let start = messages.first().map_or(0, |(time_stamp, _)| *time_stamp);
+        let ticks_per_micro = options.ppq as f64 * options.bpm / 60_000_000.0;
+
+        // Messages in arrival order with their channel, SysEx has none
+        let mut events: Vec<(u64, Option<u8>, EventKind)> = Vec::new();
+        for (time_stamp, message) in messages {
+            let tick = (time_stamp.saturating_sub(start) as f64 * ticks_per_micro).round() as u64;
+            match message.first() {
+                Some(0x80..=0xEF) => events.push((tick, Some(message[0] & 0x0F), EventKind::Midi(message.clone()))),
+                Some(0xF0) if message.last() == Some(&0xF7) => events.push((tick, None, EventKind::SysEx(message.clone()))),
+                _ => {}
+            }
+        }
+
+        let format = if options.split_channels { 1 } else { options.format };
+        let tracks = if format == 0 {
+            let events = events.into_iter().map(|(tick, _, kind)| (tick, kind)).collect();
+            vec![to_track(events, vec![tempo_event(options.bpm)])]
+        } else if options.split_channels {
+            let mut tracks = vec![vec![track_name_event("mot recording"), tempo_event(options.bpm)]];
+            let mut channels: Vec<Vec<(u64, EventKind)>> = vec![Vec::new(); 17];
+            for (tick, channel, kind) in events {
+                channels[channel.map_or(16, |c| c as usize)].push((tick, kind));
+            }
+            for (channel, events) in channels.into_iter().enumerate() {
+                if !events.is_empty() {
+                    let name = if channel < 16 { format!("Channel {}", channel + 1) } else { String::from("SysEx") };
+                    tracks.push(to_track(events, vec![track_name_event(&name)]));
+                }
+            }
+            tracks
+        } else {
+            let events = events.into_iter().map(|(tick, _, kind)| (tick, kind)).collect();
+            vec![
+                vec![track_name_event("mot recording"), tempo_event(options.bpm)],
+                to_track(events, Vec::new()),
+            ]
+        };
+
+        Smf {
+            format,
+            timing: Timing::Metrical(options.ppq),
+            tracks,
+        }
+    }
+
+    /// Encodes the file. An end of track event is added to tracks without one.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(b"MThd");
+        out.extend(6u32.to_be_bytes());
+        out.extend(self.format.to_be_bytes());
+        out.extend((self.tracks.len() as u16).to_be_bytes());
+        match self.timing {
+            Timing::Metrical(ppq) => out.extend((ppq & 0x7FFF).to_be_bytes()),
+            Timing::Timecode { fps, ticks_per_frame } => out.extend([(fps as i8).wrapping_neg() as u8, ticks_per_frame]),
+        }
+
+        for track in &self.tracks {
+            let mut data = Vec::new();
+            for event in track {
+                write_vlq(&mut data, event.delta);
+                match &event.kind {
+                    EventKind::Midi(bytes) => data.extend(bytes),
+                    EventKind::SysEx(bytes) => {
+                        data.push(0xF0);
+                        write_vlq(&mut data, bytes.len() as u32 - 1);
+                        data.extend(&bytes[1..]);
+                    }
+                    EventKind::Escape(bytes) => {
+                        data.push(0xF7);
+                        write_vlq(&mut data, bytes.len() as u32);
+                        data.extend(bytes);
+                    }
+                    EventKind::Meta(meta_type, bytes) => {
+                        data.push(0xFF);
+                        data.push(*meta_type);
+                        write_vlq(&mut data, bytes.len() as u32);
+                        data.extend(bytes);
+                    }
+                }
+            }
+            let ends = matches!(track.last(), Some(TrackEvent { kind: EventKind::Meta(META_END_OF_TRACK, _), .. }));
+            if !ends {
+                data.extend([0x00, 0xFF, META_END_OF_TRACK, 0x00]);
+            }
+            out.extend(b"MTrk");
+            out.extend((data.len() as u32).to_be_bytes());
+            out.extend(data);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_vlq() {
+        for (value, expected) in [
+            (0u32, vec![0x00]),
+            (0x40, vec![0x40]),
+            (0x7F, vec![0x7F]),
+            (0x80, vec![0x81, 0x00]),
+            (0x2000, vec![0xC0, 0x00]),
+            (0x0FFF_FFFF, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+        ] {
+            let mut out = Vec::new();
+            write_vlq(&mut out, value);
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn test_tempo_range() {
+        let tempo = |bpm: f64| match tempo_event(bpm).kind {
+            EventKind::Meta(META_TEMPO, data) => data,
+            other => panic!("expected a tempo event, got {:?}", other),
+        };
+        assert_eq!(tempo(120.0), vec![0x07, 0xA1, 0x20]);
+        assert_eq!(tempo(MIN_BPM), vec![0xFF, 0xFF, 0xFF]);
+        assert_eq!(tempo(3.0), vec![0xFF, 0xFF, 0xFF]);
+        assert_eq!(tempo(MAX_BPM), vec![0x00, 0x00, 0x01]);
+        assert_eq!(tempo(f64::MAX), vec![0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_recording_clock_with_interleaved_ports() {
+        let mut clock = RecordingClock::new();
+        // Port a counts from long before the recording, port b from just now
+        assert_eq!(clock.stamp("a", 1_000_000, 0), 0);
+        assert_eq!(clock.stamp("b", 50, 10), 10);
+        assert_eq!(clock.stamp("a", 1_000_500, 530), 500);
+        assert_eq!(clock.stamp("b", 560, 540), 520);
+        // Many interleaved messages do not drift
+        for i in 1..=1000u64 {
+            assert_eq!(clock.stamp("a", 1_000_500 + i * 1000, 530 + i * 1000), 500 + i * 1000);
+            assert_eq!(clock.stamp("b", 560 + i * 1000, 545 + i * 1000), 520 + i * 1000);
+        }
+        // Port a reconnects after a while with restarted timestamps: the gap stays
+        assert_eq!(clock.stamp("a", 20, 5_000_000), 5_000_000);
+        assert_eq!(clock.stamp("a", 1_020, 5_001_010), 5_001_000);
+        assert_eq!(clock.stamp("b", 4_999_560, 4_999_545), 4_999_520);
+    }
+
+    #[test]
+    fn test_record_format_0() {
+        let messages = vec![
+            (1_000_000, vec![0x90, 60, 100]),
+            (1_500_000, vec![0xF8]),
+            (1_500_000, vec![0x80, 60, 0]),
+        ];
+        let options = RecordOptions { ppq: 480, bpm: 120.0, format: 0, split_channels: false };
+        let smf = Smf::from_recording(&messages, &options);
+        assert_eq!(smf.tracks.len(), 1);
+        assert_eq!(
+            smf.tracks[0],
+            vec![
+                TrackEvent { delta: 0, kind: EventKind::Meta(META_TEMPO, vec![0x07, 0xA1, 0x20]) },
+                TrackEvent { delta: 0, kind: EventKind::Midi(vec![0x90, 60, 100]) },
+                TrackEvent { delta: 480, kind: EventKind::Midi(vec![0x80, 60, 0]) },
+            ]
+        );
+
+        let bytes = smf.encode();
+        assert_eq!(&bytes[..14], &[b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xE0]);
+        assert_eq!(&bytes[14..18], b"MTrk");
+        assert_eq!(
+            &bytes[22..],
+            &[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, 0x00, 0x90, 60, 100, 0x83, 0x60, 0x80, 60, 0, 0x00, 0xFF, 0x2F, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_parse_running_status_and_meta() {
+        let mut bytes = vec![b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x00, 0x60];
+        let track = [
+            0x00, 0xFF, 0x03, 0x04, b'l', b'e', b'a', b'd',
+            0x00, 0x90, 60, 100,
+            0x60, 64, 100, // running status
+            0x00, 0xF0, 0x03, 0x7E, 0x01, 0xF7,
+            0x81, 0x40, 0x80, 60, 0,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        bytes.extend(b"MTrk");
+        bytes.extend((track.len() as u32).to_be_bytes());
+        bytes.extend(track);
+
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.format, 0);
+        assert_eq!(smf.timing, Timing::Metrical(96));
+        assert_eq!(
+            smf.tracks[0],
+            vec![
+                TrackEvent { delta: 0, kind: EventKind::Meta(META_TRACK_NAME, b"lead".to_vec()) },
+                TrackEvent { delta: 0, kind: EventKind::Midi(vec![0x90, 60, 100]) },
+                TrackEvent { delta: 96, kind: EventKind::Midi(vec![0x90, 64, 100]) },
+                TrackEvent { delta: 0, kind: EventKind::SysEx(vec![0xF0, 0x7E, 0x01, 0xF7]) },
+                TrackEvent { delta: 192, kind: EventKind::Midi(vec![0x80, 60, 0]) },
+                TrackEvent { delta: 0, kind: EventKind::Meta(META_END_OF_TRACK, vec![]) },
+            ]
+        );
+        assert_eq!(Smf::parse(&smf.encode()).unwrap(), smf);
+        assert!(Smf::parse(b"RIFF").is_err());
+    }
+
+    #[test]
+    fn test_timed_messages_follow_tempo_map() {
+        let smf = Smf {
+            format: 1,
+            timing: Timing::Metrical(100),
+            tracks: vec![
+                vec![
+                    TrackEvent { delta: 100, kind: EventKind::Meta(META_TEMPO, vec![0x0F, 0x42, 0x40]) },
+                ],
+                vec![
+                    TrackEvent { delta: 100, kind: EventKind::Midi(vec![0x90, 60, 100]) },
+                    TrackEvent { delta: 100, kind: EventKind::Midi(vec![0x80, 60, 0]) },
+                ],
+            ],
+        };
+        // 100 ticks at 120 BPM take 0.5s, the next 100 ticks at 60 BPM take 1s
+        assert_eq!(
+            smf.timed_messages(),
+            vec![(500_000, vec![0x90, 60, 100]), (1_500_000, vec![0x80, 60, 0])]
+        );
+
+        let sequences = Smf { format: 2, ..smf.clone() };
+        assert_eq!(sequences.timed_messages()[0], (1_000_000, vec![0x90, 60, 100]));
+
+        let timecode = Smf {
+            format: 0,
+            timing: Timing::Timecode { fps: 25, ticks_per_frame: 40 },
+            tracks: vec![vec![TrackEvent { delta: 1000, kind: EventKind::Midi(vec![0xC0, 1]) }]],
+        };
+        assert_eq!(timecode.timed_messages(), vec![(1_000_000, vec![0xC0, 1])]);
+    }
+
+    #[test]
+    fn test_record_split_channels() {
+        let messages = vec![
+            (0, vec![0x90, 60, 100]),
+            (10, vec![0x91, 64, 100]),
+            (20, vec![0xF0, 0x7E, 0xF7]),
+        ];
+        let options = RecordOptions { ppq: 96, bpm: 120.0, format: 0, split_channels: true };
+        let smf = Smf::from_recording(&messages, &options);
+        assert_eq!(smf.format, 1);
+        assert_eq!(smf.tracks.len(), 4);
+        assert_eq!(smf.tracks[2][1].kind, EventKind::Midi(vec![0x91, 64, 100]));
+        assert_eq!(smf.tracks[3][1].kind, EventKind::SysEx(vec![0xF0, 0x7E, 0xF7]));
+    }
+}
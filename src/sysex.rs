@@ -0,0 +1,64 @@
+use std::path::Path;
+
+/// Splits the contents of a `.syx` file into SysEx messages from 0xF0 up to and
+/// including 0xF7. Bytes between messages are skipped, as are messages which are
+/// cut short by another status byte or the end of the file.
+pub fn split_messages(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+    for &byte in data {
+        match byte {
+            0xF0 => current = Some(vec![byte]),
+            0xF7 => {
+                if let Some(mut message) = current.take() {
+                    message.push(byte);
+                    messages.push(message);
+                }
+            }
+            0x80..=0xFF => current = None,
+            _ => {
+                if let Some(message) = current.as_mut() {
+                    message.push(byte);
+                }
+            }
+        }
+    }
+    messages
+}
+
+/// The path for message `index` when every message gets its own file, e.g.
+/// `dump_003.syx` for `dump.syx`.
+pub fn numbered_path(path: &str, index: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("sysex");
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("syx");
+    path.with_file_name(format!("{}_{:03}.{}", stem, index, extension))
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_messages() {
+        let data = [
+            0xF0, 0x7E, 0x01, 0xF7, // complete
+            0x00, 0x12, // garbage between messages
+            0xF0, 0x43, 0x10, 0xF7, // complete
+            0xF0, 0x41, 0x90, 0x3C, 0xF7, // interrupted by a note on
+            0xF0, 0x41, // truncated
+        ];
+        assert_eq!(
+            split_messages(&data),
+            vec![vec![0xF0, 0x7E, 0x01, 0xF7], vec![0xF0, 0x43, 0x10, 0xF7]]
+        );
+    }
+
+    #[test]
+    fn test_numbered_path() {
+        assert_eq!(numbered_path("dump.syx", 3), "dump_003.syx");
+        assert_eq!(numbered_path("patches/juno", 12), "patches/juno_012.syx");
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest single sleep, so Ctrl+C is noticed while waiting for a distant deadline.
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+/// The last part before a deadline is spent yielding instead of sleeping: sleeps
+/// easily overshoot by a millisecond or more.
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+/// Waits until the deadline. Returns false as soon as `running` is cleared.
+pub fn sleep_until(deadline: Instant, running: &AtomicBool) -> bool {
+    loop {
+        if !running.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        let remaining = deadline - now;
+        if remaining > SPIN_MARGIN {
+            thread::sleep((remaining - SPIN_MARGIN).min(MAX_SLEEP));
+        } else {
+            thread::yield_now();
+        }
+    }
+}